@@ -0,0 +1,197 @@
+//! Learning the delay of every key from how it bounces, instead of using one fixed delay.
+//!
+//! [`DelayLearner`] keeps the last intervals between presses of every key, sorted into
+//! bounces and deliberate presses as [`calibration`](crate::calibration) does, and places
+//! the key's delay in the gap between the two once it has seen enough presses. Outliers
+//! are left out: a bounce slower than most, or a deliberate press faster than most, does
+//! not move the delay on its own.
+
+use crate::calibration::{KeyTiming, Timings, delay_between};
+use crate::engine::{KeyEvent, Timestamp};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Number of bounces, and of deliberate presses, kept for every key.
+pub const WINDOW: usize = 100;
+/// Number of presses of a key, besides its first, seen before its delay is learned.
+pub const MIN_PRESSES: usize = 20;
+/// Share of the bounces a learned delay suppresses for sure.
+const BOUNCE_PERCENTILE: f64 = 0.95;
+/// Share of the deliberate presses faster than the one a learned delay is placed below.
+const DELIBERATE_PERCENTILE: f64 = 0.05;
+
+/// Bounds of learned delays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveConfig {
+    /// Delay of keys that do not bounce, and shortest delay learned.
+    pub floor: Duration,
+    /// Longest delay learned.
+    pub ceiling: Duration,
+}
+
+impl AdaptiveConfig {
+    fn clamp(&self, delay: Duration) -> Duration {
+        delay.min(self.ceiling).max(self.floor)
+    }
+}
+
+/// Learns the delay of every key it observes.
+#[derive(Debug)]
+pub struct DelayLearner<K, T> {
+    config: AdaptiveConfig,
+    timings: Timings<K, T>,
+    learned: BTreeMap<K, Duration>,
+}
+
+impl<K, T> DelayLearner<K, T>
+where
+    K: Copy + Eq + Hash + Ord,
+    T: Timestamp,
+{
+    pub fn new(config: AdaptiveConfig) -> DelayLearner<K, T> {
+        DelayLearner {
+            config,
+            timings: Timings::rolling(WINDOW),
+            learned: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> AdaptiveConfig {
+        self.config
+    }
+
+    /// Replaces the bounds, moving the delays learned so far within them.
+    pub fn set_config(&mut self, config: AdaptiveConfig) {
+        self.config = config;
+        for delay in self.learned.values_mut() {
+            *delay = config.clamp(*delay);
+        }
+    }
+
+    /// Records an event, learning the delay of its key again when it is a press. Events
+    /// have to be given in the order they happened.
+    pub fn observe(&mut self, event: &KeyEvent<K, T>) {
+        if self.timings.observe(event).is_none() {
+            return;
+        }
+        if let Some(delay) = estimate(&self.timings.keys()[&event.code], self.config) {
+            self.learned.insert(event.code, delay);
+        }
+    }
+
+    /// Returns the delay learned for a key, if any.
+    pub fn delay(&self, key: &K) -> Option<Duration> {
+        self.learned.get(key).copied()
+    }
+
+    /// Returns the delays learned so far.
+    pub fn learned(&self) -> &BTreeMap<K, Duration> {
+        &self.learned
+    }
+
+    /// Takes up delays learned earlier, as by a previous run, until keys are pressed
+    /// enough to learn them again.
+    pub fn restore(&mut self, delays: impl IntoIterator<Item = (K, Duration)>) {
+        for (key, delay) in delays {
+            self.learned.insert(key, self.config.clamp(delay));
+        }
+    }
+}
+
+/// Returns the delay between most of a key's bounces and most of its deliberate presses,
+/// within the bounds, or `None` until enough presses were seen, see [`MIN_PRESSES`].
+pub fn estimate(timing: &KeyTiming, config: AdaptiveConfig) -> Option<Duration> {
+    if timing.bounces.len() + timing.deliberate.len() < MIN_PRESSES {
+        return None;
+    }
+    let delay = match percentile(&timing.bounces, BOUNCE_PERCENTILE) {
+        Some(bounce) => delay_between(
+            bounce,
+            percentile(&timing.deliberate, DELIBERATE_PERCENTILE),
+        ),
+        None => config.floor,
+    };
+    Some(config.clamp(delay))
+}
+
+/// Returns the interval that a share `p` of `intervals` is shorter than or as long as.
+fn percentile(intervals: &[Duration], p: f64) -> Option<Duration> {
+    let mut sorted = intervals.to_vec();
+    sorted.sort_unstable();
+    let last = sorted.len().checked_sub(1)?;
+    Some(sorted[(last as f64 * p).round() as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{PRESS, RELEASE};
+
+    const KEY_A: u16 = 30;
+    const KEY_S: u16 = 31;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    const BOUNDS: AdaptiveConfig = AdaptiveConfig {
+        floor: Duration::from_millis(10),
+        ceiling: Duration::from_millis(150),
+    };
+
+    fn timing(bounces: &[u64], deliberate: &[u64]) -> KeyTiming {
+        KeyTiming {
+            bounces: bounces.iter().copied().map(ms).collect(),
+            deliberate: deliberate.iter().copied().map(ms).collect(),
+        }
+    }
+
+    #[test]
+    fn estimates_between_most_bounces_and_deliberate_presses() {
+        let mut bounces = vec![5; 19];
+        bounces.push(60);
+        let mut deliberate = vec![150; 19];
+        deliberate.push(30);
+        assert_eq!(
+            estimate(&timing(&bounces, &deliberate), BOUNDS),
+            Some(ms(28))
+        );
+
+        assert_eq!(estimate(&timing(&[5; 10], &[150; 9]), BOUNDS), None);
+        assert_eq!(estimate(&timing(&[], &[150; 20]), BOUNDS), Some(ms(10)));
+        assert_eq!(estimate(&timing(&[1; 20], &[]), BOUNDS), Some(ms(10)));
+        assert_eq!(estimate(&timing(&[100; 20], &[]), BOUNDS), Some(ms(150)));
+    }
+
+    #[test]
+    fn learns_keys_that_chatter() {
+        let mut learner = DelayLearner::new(BOUNDS);
+        for i in 0..=MIN_PRESSES as u64 / 2 {
+            let at = i * 150;
+            for (code, value, at) in [
+                (KEY_A, PRESS, at),
+                (KEY_A, RELEASE, at + 3),
+                (KEY_A, PRESS, at + 5),
+                (KEY_A, RELEASE, at + 8),
+                (KEY_S, PRESS, at + 20),
+                (KEY_S, RELEASE, at + 60),
+            ] {
+                learner.observe(&KeyEvent::new(code, value, ms(at)));
+            }
+        }
+        assert_eq!(learner.delay(&KEY_A), Some(ms(27)));
+        assert_eq!(learner.delay(&KEY_S), None);
+
+        learner.restore([(KEY_S, ms(500))]);
+        assert_eq!(learner.delay(&KEY_S), Some(ms(150)));
+        learner.set_config(AdaptiveConfig {
+            floor: ms(30),
+            ceiling: ms(100),
+        });
+        assert_eq!(
+            learner.learned(),
+            &BTreeMap::from([(KEY_A, ms(30)), (KEY_S, ms(100))])
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Finding the keys that bounce, so that only those are debounced.
+//!
+//! Debouncing keys whose switches are fine only adds to the risk of eating deliberate
+//! presses. [`BounceFinder`] watches every key that may be debounced and picks out those
+//! that bounced a few times over their recent presses, as told by
+//! [`calibration`](crate::calibration). While it learns, every key is debounced; once it
+//! has seen enough presses, only the keys it found are, and it keeps watching the others
+//! for switches that start failing later.
+
+use crate::calibration::Timings;
+use crate::engine::{KeyEvent, PRESS, Timestamp};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Number of the latest presses of a key its bounces are counted over.
+pub const RECENT_PRESSES: u64 = 100;
+
+/// Settings of a [`BounceFinder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LearningConfig {
+    /// Number of presses, of every key watched, seen before only the keys found bouncing
+    /// are debounced.
+    pub presses: u64,
+    /// Number of bounces over [`RECENT_PRESSES`] presses of a key that make it bouncing.
+    pub bounces: usize,
+}
+
+/// Presses of a key, and the bounces among the recent ones.
+#[derive(Debug, Default)]
+struct KeyPresses {
+    presses: u64,
+    /// Which press of the key every recent bounce was.
+    bounces: VecDeque<u64>,
+}
+
+/// Finds the keys that bounce among the ones it observes.
+#[derive(Debug)]
+pub struct BounceFinder<K, T> {
+    config: LearningConfig,
+    /// Only whether the latest press of every key was a bounce is needed.
+    timings: Timings<K, T>,
+    keys: HashMap<K, KeyPresses>,
+    found: BTreeSet<K>,
+    presses: u64,
+}
+
+impl<K, T> BounceFinder<K, T>
+where
+    K: Copy + Eq + Hash + Ord,
+    T: Timestamp,
+{
+    pub fn new(config: LearningConfig) -> BounceFinder<K, T> {
+        BounceFinder {
+            config,
+            timings: Timings::rolling(1),
+            keys: HashMap::new(),
+            found: BTreeSet::new(),
+            presses: 0,
+        }
+    }
+
+    pub fn set_config(&mut self, config: LearningConfig) {
+        self.config = config;
+    }
+
+    /// Records an event, returning whether its key was just found bouncing. Events have
+    /// to be given in the order they happened.
+    pub fn observe(&mut self, event: &KeyEvent<K, T>) -> bool {
+        let measured = self.timings.observe(event);
+        if event.value != PRESS {
+            return false;
+        }
+        self.presses += 1;
+        let key = self.keys.entry(event.code).or_default();
+        key.presses += 1;
+        if !measured.is_some_and(|press| press.bounce) {
+            return false;
+        }
+        key.bounces.push_back(key.presses);
+        while key
+            .bounces
+            .front()
+            .is_some_and(|&first| key.presses - first >= RECENT_PRESSES)
+        {
+            key.bounces.pop_front();
+        }
+        key.bounces.len() >= self.config.bounces && self.found.insert(event.code)
+    }
+
+    /// Returns whether not enough presses were seen yet to tell which keys bounce.
+    pub fn is_learning(&self) -> bool {
+        self.presses < self.config.presses
+    }
+
+    /// Returns whether a key has to be debounced: any key while learning, then only the
+    /// keys found bouncing.
+    pub fn needs_debouncing(&self, key: &K) -> bool {
+        self.is_learning() || self.found.contains(key)
+    }
+
+    /// Returns the keys found bouncing so far.
+    pub fn found(&self) -> &BTreeSet<K> {
+        &self.found
+    }
+
+    /// Returns the number of presses seen so far.
+    pub fn presses(&self) -> u64 {
+        self.presses
+    }
+
+    /// Takes up what was found earlier, as by a previous run.
+    pub fn restore(&mut self, found: impl IntoIterator<Item = K>, presses: u64) {
+        self.found.extend(found);
+        self.presses = self.presses.max(presses);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::RELEASE;
+    use std::time::Duration;
+
+    const KEY_A: u16 = 30;
+    const KEY_S: u16 = 31;
+
+    /// Types KEY_S, then KEY_A bouncing every `every` presses, starting at `start` ms.
+    fn type_keys(finder: &mut BounceFinder<u16, Duration>, start: u64, presses: u64, every: u64) {
+        let ms = Duration::from_millis;
+        for i in 0..presses {
+            let at = start + i * 300;
+            for (code, value, at) in [
+                (KEY_S, PRESS, at),
+                (KEY_S, RELEASE, at + 50),
+                (KEY_A, PRESS, at + 100),
+                (KEY_A, RELEASE, at + 150),
+            ] {
+                finder.observe(&KeyEvent::new(code, value, ms(at)));
+            }
+            if i % every == every - 1 {
+                finder.observe(&KeyEvent::new(KEY_A, PRESS, ms(at + 155)));
+                finder.observe(&KeyEvent::new(KEY_A, RELEASE, ms(at + 158)));
+            }
+        }
+    }
+
+    #[test]
+    fn finds_keys_bouncing_over_their_recent_presses() {
+        let mut finder = BounceFinder::new(LearningConfig {
+            presses: 100,
+            bounces: 2,
+        });
+        assert!(finder.needs_debouncing(&KEY_S));
+        // Bounces more than 100 presses apart are not enough.
+        type_keys(&mut finder, 0, 250, 120);
+        assert!(!finder.is_learning());
+        assert!(finder.found().is_empty());
+        assert!(!finder.needs_debouncing(&KEY_A));
+
+        type_keys(&mut finder, 100_000, 10, 5);
+        assert_eq!(finder.found(), &BTreeSet::from([KEY_A]));
+        assert!(finder.needs_debouncing(&KEY_A));
+        assert!(!finder.needs_debouncing(&KEY_S));
+
+        let mut restored = BounceFinder::<u16, Duration>::new(finder.config);
+        restored.restore([KEY_A], finder.presses());
+        assert!(!restored.is_learning());
+        assert!(restored.needs_debouncing(&KEY_A));
+    }
+}
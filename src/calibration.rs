@@ -0,0 +1,327 @@
+//! Measuring how keys bounce, to pick delays that suppress bounces without eating presses
+//! made on purpose.
+//!
+//! A switch bounces by breaking contact for a few milliseconds, so a bounce is a press that
+//! follows the release of the same key by less than [`BOUNCE_GAP`]: nobody lifts a finger
+//! and presses again that fast. [`Timings`] sorts the presses it observes this way, and
+//! [`recommend`] picks delays from the result.
+
+use crate::engine::{KeyEvent, PRESS, RELEASE, Timestamp};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Longest time between the release of a key and a press that is a bounce.
+pub const BOUNCE_GAP: Duration = Duration::from_millis(20);
+
+/// Intervals between a press and the previous press of the same key.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KeyTiming {
+    /// Intervals of presses that were bounces.
+    pub bounces: Vec<Duration>,
+    /// Intervals of presses made on purpose.
+    pub deliberate: Vec<Duration>,
+}
+
+impl KeyTiming {
+    pub fn longest_bounce(&self) -> Option<Duration> {
+        self.bounces.iter().max().copied()
+    }
+
+    pub fn fastest_deliberate(&self) -> Option<Duration> {
+        self.deliberate.iter().min().copied()
+    }
+
+    /// Returns the delay that best tells this key's bounces from its deliberate presses,
+    /// or `None` when it did not bounce.
+    pub fn delay(&self) -> Option<Duration> {
+        let bounce = self.longest_bounce()?;
+        Some(delay_between(bounce, self.fastest_deliberate()))
+    }
+
+    fn push(&mut self, interval: Duration, bounce: bool, window: Option<usize>) {
+        let intervals = match bounce {
+            true => &mut self.bounces,
+            false => &mut self.deliberate,
+        };
+        if window.is_some_and(|window| intervals.len() >= window) {
+            intervals.remove(0);
+        }
+        intervals.push(interval);
+    }
+}
+
+/// Returns the delay between bounces up to `bounce` apart and deliberate presses from
+/// `deliberate` apart, rounded up to whole milliseconds.
+///
+/// The delay sits halfway between them on a logarithmic scale, as both vary in proportion
+/// rather than by a fixed amount. Bounces as far apart as deliberate presses cannot be told
+/// apart by a delay, which then stays just below the deliberate presses.
+pub fn delay_between(bounce: Duration, deliberate: Option<Duration>) -> Duration {
+    let delay = match deliberate {
+        Some(deliberate) if deliberate <= bounce => deliberate.saturating_sub(MILLISECOND),
+        Some(deliberate) => {
+            let between = (bounce.as_secs_f64() * deliberate.as_secs_f64()).sqrt();
+            Duration::from_secs_f64(between).max(bounce + MILLISECOND)
+        }
+        None => bounce * 2,
+    };
+    round_up(delay)
+}
+
+const MILLISECOND: Duration = Duration::from_millis(1);
+
+fn round_up(duration: Duration) -> Duration {
+    Duration::from_millis(duration.as_nanos().div_ceil(1_000_000) as u64).max(MILLISECOND)
+}
+
+/// When a key was last pressed and released.
+#[derive(Debug)]
+struct LastEvents<T> {
+    press: Option<T>,
+    release: Option<T>,
+}
+
+/// A press observed by [`Timings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Press {
+    /// Time since the previous press of the same key.
+    pub interval: Duration,
+    pub bounce: bool,
+}
+
+/// Sorts the presses of every key into bounces and deliberate presses.
+#[derive(Debug)]
+pub struct Timings<K, T> {
+    last: HashMap<K, LastEvents<T>>,
+    keys: BTreeMap<K, KeyTiming>,
+    /// Number of intervals of each kind kept per key, every one when `None`.
+    window: Option<usize>,
+}
+
+impl<K, T> Default for Timings<K, T> {
+    fn default() -> Timings<K, T> {
+        Timings {
+            last: HashMap::new(),
+            keys: BTreeMap::new(),
+            window: None,
+        }
+    }
+}
+
+impl<K, T> Timings<K, T>
+where
+    K: Copy + Eq + Hash + Ord,
+    T: Timestamp,
+{
+    pub fn new() -> Timings<K, T> {
+        Timings::default()
+    }
+
+    /// Keeps only the last `window` bounces and deliberate presses of every key.
+    pub fn rolling(window: usize) -> Timings<K, T> {
+        Timings {
+            window: Some(window),
+            ..Timings::default()
+        }
+    }
+
+    /// Records an event, returning what was measured when it is a press that follows
+    /// another press of the same key. Events have to be given in the order they happened.
+    pub fn observe(&mut self, event: &KeyEvent<K, T>) -> Option<Press> {
+        let last = self.last.entry(event.code).or_insert(LastEvents {
+            press: None,
+            release: None,
+        });
+        match event.value {
+            PRESS => {}
+            RELEASE => {
+                last.release = Some(event.timestamp);
+                return None;
+            }
+            _ => return None,
+        }
+        let previous = last.press.replace(event.timestamp)?;
+        let interval = event.timestamp.checked_duration_since(previous)?;
+        // Without a release in between, as when the release was missed, the interval
+        // itself has to be short.
+        let gap = last
+            .release
+            .filter(|&release| release.checked_duration_since(previous).is_some())
+            .and_then(|release| event.timestamp.checked_duration_since(release))
+            .unwrap_or(interval);
+        let bounce = gap < BOUNCE_GAP;
+        let timing = self.keys.entry(event.code).or_default();
+        timing.push(interval, bounce, self.window);
+        Some(Press { interval, bounce })
+    }
+
+    /// Returns what was measured for every key pressed at least twice.
+    pub fn keys(&self) -> &BTreeMap<K, KeyTiming> {
+        &self.keys
+    }
+
+    /// Forgets a key, as when it is measured again.
+    pub fn forget(&mut self, key: K) {
+        self.last.remove(&key);
+        self.keys.remove(&key);
+    }
+}
+
+/// Delays that suppress every bounce measured without eating deliberate presses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recommendation<K> {
+    /// Delay for every key, `None` when no key bounced.
+    pub delay: Option<Duration>,
+    /// Keys the delay does not suit, with their own.
+    pub key_delays: BTreeMap<K, Duration>,
+}
+
+/// Recommends the median of the delays that suit every key that bounced, and delays of
+/// their own for keys it would not suit: keys whose bounces it would let through, or
+/// whose deliberate presses it would eat.
+pub fn recommend<K: Copy + Ord>(keys: &BTreeMap<K, KeyTiming>) -> Recommendation<K> {
+    let mut delays = keys
+        .values()
+        .filter_map(KeyTiming::delay)
+        .collect::<Vec<_>>();
+    delays.sort_unstable();
+    let Some(&delay) = delays.get(delays.len().saturating_sub(1) / 2) else {
+        return Recommendation {
+            delay: None,
+            key_delays: BTreeMap::new(),
+        };
+    };
+
+    let key_delays = keys
+        .iter()
+        .filter(|(_, timing)| {
+            timing
+                .longest_bounce()
+                .is_some_and(|bounce| delay <= bounce)
+                || timing
+                    .fastest_deliberate()
+                    .is_some_and(|fastest| delay >= fastest)
+        })
+        .filter_map(|(&key, timing)| {
+            let own = timing.delay().or_else(|| {
+                timing
+                    .fastest_deliberate()
+                    .map(|fastest| round_up(fastest / 2))
+            })?;
+            Some((key, own))
+        })
+        .collect();
+    Recommendation {
+        delay: Some(delay),
+        key_delays,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: u16 = 30;
+    const KEY_S: u16 = 31;
+    const KEY_D: u16 = 32;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn timing(bounces: &[u64], deliberate: &[u64]) -> KeyTiming {
+        KeyTiming {
+            bounces: bounces.iter().copied().map(ms).collect(),
+            deliberate: deliberate.iter().copied().map(ms).collect(),
+        }
+    }
+
+    #[test]
+    fn tells_bounces_from_deliberate_presses() {
+        let mut timings = Timings::new();
+        let events = [
+            (PRESS, 0),
+            (RELEASE, 3),
+            (PRESS, 6),
+            (RELEASE, 40),
+            (PRESS, 100),
+            (RELEASE, 150),
+            (PRESS, 155),
+            (RELEASE, 160),
+        ];
+        let presses = events
+            .map(|(value, at)| timings.observe(&KeyEvent::new(KEY_A, value, ms(at))))
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            presses,
+            [
+                Press {
+                    interval: ms(6),
+                    bounce: true
+                },
+                Press {
+                    interval: ms(94),
+                    bounce: false
+                },
+                Press {
+                    interval: ms(55),
+                    bounce: true
+                },
+            ]
+        );
+        assert_eq!(timings.keys()[&KEY_A], timing(&[6, 55], &[94]));
+
+        timings.forget(KEY_A);
+        assert!(timings.keys().is_empty());
+        assert_eq!(timings.observe(&KeyEvent::new(KEY_A, PRESS, ms(200))), None);
+    }
+
+    #[test]
+    fn rolling_timings_keep_the_last_intervals() {
+        let mut timings = Timings::rolling(2);
+        for (i, at) in [0, 100, 300, 600].into_iter().enumerate() {
+            timings.observe(&KeyEvent::new(KEY_A, PRESS, ms(at)));
+            timings.observe(&KeyEvent::new(KEY_A, RELEASE, ms(at + 50 + i as u64)));
+        }
+        assert_eq!(timings.keys()[&KEY_A], timing(&[], &[200, 300]));
+    }
+
+    #[test]
+    fn key_delay_sits_between_bounces_and_deliberate_presses() {
+        assert_eq!(timing(&[4, 9], &[81, 120]).delay(), Some(ms(27)));
+        assert_eq!(timing(&[12], &[]).delay(), Some(ms(24)));
+        assert_eq!(timing(&[90], &[80]).delay(), Some(ms(79)));
+        assert_eq!(timing(&[], &[80]).delay(), None);
+    }
+
+    #[test]
+    fn recommends_key_delays_where_the_delay_does_not_suit() {
+        let keys = BTreeMap::from([
+            (KEY_A, timing(&[4, 9], &[81, 120])),
+            (KEY_S, timing(&[40], &[160])),
+            (KEY_D, timing(&[], &[50])),
+        ]);
+        assert_eq!(
+            recommend(&keys),
+            Recommendation {
+                delay: Some(ms(27)),
+                key_delays: BTreeMap::from([(KEY_S, ms(80))]),
+            }
+        );
+
+        let keys = BTreeMap::from([(KEY_A, timing(&[4], &[100])), (KEY_D, timing(&[], &[30]))]);
+        assert_eq!(
+            recommend(&keys),
+            Recommendation {
+                delay: Some(ms(20)),
+                key_delays: BTreeMap::new(),
+            }
+        );
+
+        let keys = BTreeMap::from([(KEY_D, timing(&[], &[60]))]);
+        assert_eq!(recommend(&keys).delay, None);
+    }
+}
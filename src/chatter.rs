@@ -0,0 +1,147 @@
+//! Lengthening the delay of keys while they chatter.
+//!
+//! A failing switch often chatters in bursts, as on humid days or after a spill, with
+//! bounces slower than usual. [`ChatterTracker`] notices a key whose presses keep being
+//! suppressed and multiplies its delay until it has been quiet for a while, then lets it
+//! fall back to its usual delay.
+
+use crate::engine::Timestamp;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Settings of a [`ChatterTracker`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChatterConfig {
+    /// Number of suppressed presses of a key within [`ChatterConfig::window`] that make it
+    /// chatter.
+    pub bounces: usize,
+    /// How far back from the latest suppressed press of a key the others are counted,
+    /// measured on the event timestamps; `chatter_window_ms` in the config file.
+    pub window: Duration,
+    /// What the delay of a chattering key is multiplied by.
+    pub factor: f64,
+    /// Time without a suppressed press after which a key no longer chatters.
+    pub quiet: Duration,
+}
+
+/// Tracks the keys that chatter, from their suppressed presses.
+#[derive(Debug)]
+pub struct ChatterTracker<K, T> {
+    config: ChatterConfig,
+    /// Suppressed presses of every key within the window, the latest last.
+    suppressed: HashMap<K, VecDeque<T>>,
+    /// Keys that chatter, with their last suppressed press.
+    chattering: HashMap<K, T>,
+}
+
+impl<K, T> ChatterTracker<K, T>
+where
+    K: Copy + Eq + Hash,
+    T: Timestamp,
+{
+    pub fn new(config: ChatterConfig) -> ChatterTracker<K, T> {
+        ChatterTracker {
+            config,
+            suppressed: HashMap::new(),
+            chattering: HashMap::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: ChatterConfig) {
+        self.config = config;
+    }
+
+    /// Records a suppressed press, returning whether its key just started chattering.
+    /// Presses have to be given in the order they happened.
+    pub fn record_suppressed(&mut self, key: K, timestamp: T) -> bool {
+        let window = self.config.window;
+        let presses = self.suppressed.entry(key).or_default();
+        // Presses stamped after this one, as when the clock went back, are dropped too.
+        presses.retain(|&press| {
+            timestamp
+                .checked_duration_since(press)
+                .is_some_and(|elapsed| elapsed <= window)
+        });
+        presses.push_back(timestamp);
+        if let Some(last) = self.chattering.get_mut(&key) {
+            *last = timestamp;
+            return false;
+        }
+        if presses.len() < self.config.bounces {
+            return false;
+        }
+        self.chattering.insert(key, timestamp);
+        true
+    }
+
+    /// Stops tracking as chattering the keys that were quiet long enough by `now`,
+    /// returning them.
+    pub fn calm_down(&mut self, now: T) -> Vec<K> {
+        let quiet = self.config.quiet;
+        let calmed = self
+            .chattering
+            .iter()
+            .filter(|&(_, &last)| {
+                now.checked_duration_since(last)
+                    .is_none_or(|elapsed| elapsed >= quiet)
+            })
+            .map(|(&key, _)| key)
+            .collect::<Vec<_>>();
+        for key in &calmed {
+            self.chattering.remove(key);
+            self.suppressed.remove(key);
+        }
+        calmed
+    }
+
+    /// Returns whether a key chatters.
+    pub fn is_chattering(&self, key: &K) -> bool {
+        self.chattering.contains_key(key)
+    }
+
+    /// Returns the delay of a key given its usual one: longer while it chatters.
+    pub fn delay(&self, key: &K, delay: Duration) -> Duration {
+        match self.is_chattering(key) {
+            true => delay.mul_f64(self.config.factor),
+            false => delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: u16 = 30;
+    const KEY_S: u16 = 31;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn keys_suppressed_in_bursts_chatter_until_quiet() {
+        let mut tracker = ChatterTracker::new(ChatterConfig {
+            bounces: 3,
+            window: ms(1000),
+            factor: 1.5,
+            quiet: ms(5000),
+        });
+        // Suppressions spread out are not a burst.
+        assert!(!tracker.record_suppressed(KEY_A, ms(0)));
+        assert!(!tracker.record_suppressed(KEY_A, ms(800)));
+        assert!(!tracker.record_suppressed(KEY_A, ms(1600)));
+        assert!(!tracker.record_suppressed(KEY_S, ms(1700)));
+        assert!(tracker.record_suppressed(KEY_A, ms(1750)));
+        assert_eq!(tracker.delay(&KEY_A, ms(40)), ms(60));
+        assert_eq!(tracker.delay(&KEY_S, ms(40)), ms(40));
+
+        // Every suppression while chattering puts off calming down.
+        assert!(!tracker.record_suppressed(KEY_A, ms(4000)));
+        assert!(tracker.calm_down(ms(8999)).is_empty());
+        assert_eq!(tracker.calm_down(ms(9000)), [KEY_A]);
+        assert!(!tracker.is_chattering(&KEY_A));
+        assert!(!tracker.record_suppressed(KEY_A, ms(9100)));
+    }
+}
@@ -0,0 +1,1779 @@
+//! Configuration files, shared by every platform.
+//!
+//! Settings are read from INI, TOML, YAML or JSON files layered with environment variables
+//! and command line overrides, and resolved to one [`ConfigHolder`]. Key names are resolved
+//! by the platform's [`Keymap`].
+
+use crate::adaptive::AdaptiveConfig;
+use crate::bouncing::LearningConfig;
+use crate::chatter::ChatterConfig;
+use crate::corrections::CorrectionConfig;
+use crate::engine::EngineConfig;
+use config::{Config, Environment, File, FileFormat, Value, ValueKind};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::Hash;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const SYSTEM_CONFIG_DIR: &str = "/etc/debounce-keyboard";
+const CONFIG_EXTENSIONS: [&str; 5] = ["ini", "toml", "yaml", "yml", "json"];
+const ENV_PREFIX: &str = "DEBOUNCE";
+const DEFAULT_DELAY_MS: u64 = 85;
+/// Name of the profile made of the top-level settings only.
+pub const DEFAULT_PROFILE: &str = "default";
+const DELAY_MS_RANGE: RangeInclusive<u64> = 1..=1000;
+const CONTROL_SOCKET_NAME: &str = "debounce-keyboard.sock";
+const DEFAULT_ADAPTIVE_MIN_MS: u64 = 10;
+const DEFAULT_ADAPTIVE_MAX_MS: u64 = 150;
+const STATE_FILE_PATH: &str = "debounce-keyboard/state.json";
+const DEFAULT_CORRECTION_KEY: &str = "KEY_BACKSPACE";
+/// Presses seen with `learn_keys` before only the keys found bouncing are debounced.
+const LEARN_KEYS_PRESSES: u64 = 2000;
+/// Bounces over the recent presses of a key that make it bouncing.
+const LEARN_KEYS_BOUNCES: usize = 2;
+const DEFAULT_CHATTER_WINDOW_MS: u64 = 1000;
+const DEFAULT_CHATTER_FACTOR: f64 = 2.0;
+const DEFAULT_CHATTER_QUIET_MS: u64 = 30_000;
+const CHATTER_WINDOW_MS_RANGE: RangeInclusive<u64> = 1..=60_000;
+const CHATTER_FACTOR_RANGE: RangeInclusive<f64> = 1.0..=10.0;
+const CHATTER_QUIET_MS_RANGE: RangeInclusive<u64> = 1..=3_600_000;
+
+/// Named groups usable in `keys` and `exclude_keys`, e.g. `keys=@letters,KEY_SPACE`.
+const KEY_GROUPS: [(&str, &[&str]); 6] = [
+    (
+        "@letters",
+        &[
+            "KEY_A", "KEY_B", "KEY_C", "KEY_D", "KEY_E", "KEY_F", "KEY_G", "KEY_H", "KEY_I",
+            "KEY_J", "KEY_K", "KEY_L", "KEY_M", "KEY_N", "KEY_O", "KEY_P", "KEY_Q", "KEY_R",
+            "KEY_S", "KEY_T", "KEY_U", "KEY_V", "KEY_W", "KEY_X", "KEY_Y", "KEY_Z",
+        ],
+    ),
+    (
+        "@digits",
+        &[
+            "KEY_1", "KEY_2", "KEY_3", "KEY_4", "KEY_5", "KEY_6", "KEY_7", "KEY_8", "KEY_9",
+            "KEY_0",
+        ],
+    ),
+    (
+        "@modifiers",
+        &[
+            "KEY_LEFTSHIFT",
+            "KEY_RIGHTSHIFT",
+            "KEY_LEFTCTRL",
+            "KEY_RIGHTCTRL",
+            "KEY_LEFTALT",
+            "KEY_RIGHTALT",
+            "KEY_LEFTMETA",
+            "KEY_RIGHTMETA",
+        ],
+    ),
+    (
+        "@numpad",
+        &[
+            "KEY_NUMLOCK",
+            "KEY_KPSLASH",
+            "KEY_KPASTERISK",
+            "KEY_KPMINUS",
+            "KEY_KPPLUS",
+            "KEY_KPENTER",
+            "KEY_KPDOT",
+            "KEY_KP0",
+            "KEY_KP1",
+            "KEY_KP2",
+            "KEY_KP3",
+            "KEY_KP4",
+            "KEY_KP5",
+            "KEY_KP6",
+            "KEY_KP7",
+            "KEY_KP8",
+            "KEY_KP9",
+        ],
+    ),
+    (
+        "@function",
+        &[
+            "KEY_F1", "KEY_F2", "KEY_F3", "KEY_F4", "KEY_F5", "KEY_F6", "KEY_F7", "KEY_F8",
+            "KEY_F9", "KEY_F10", "KEY_F11", "KEY_F12",
+        ],
+    ),
+    (
+        "@navigation",
+        &[
+            "KEY_UP",
+            "KEY_DOWN",
+            "KEY_LEFT",
+            "KEY_RIGHT",
+            "KEY_HOME",
+            "KEY_END",
+            "KEY_PAGEUP",
+            "KEY_PAGEDOWN",
+            "KEY_INSERT",
+            "KEY_DELETE",
+        ],
+    ),
+];
+
+/// Names the keys of a platform, in config files and anywhere keys are shown.
+pub trait Keymap {
+    /// The platform's key identifier, such as an evdev key code.
+    type Key: Copy + Eq + Hash + Ord + Debug;
+
+    /// Resolves a key name such as `KEY_A`, or a numeric code such as `0x1e`.
+    fn key(name: &str) -> Option<Self::Key>;
+
+    /// Returns the name of a key, as accepted by [`Keymap::key`].
+    fn name(key: Self::Key) -> String;
+
+    /// Returns every key that can be debounced, used when `keys` is not set.
+    fn all_keys() -> Vec<Self::Key>;
+}
+
+/// Format of a config file, detected from its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Ini,
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Files without a recognised extension are read as INI.
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Ini,
+        }
+    }
+
+    fn file_format(self) -> FileFormat {
+        match self {
+            ConfigFormat::Ini => FileFormat::Ini,
+            ConfigFormat::Toml => FileFormat::Toml,
+            ConfigFormat::Yaml => FileFormat::Yaml,
+            ConfigFormat::Json => FileFormat::Json,
+        }
+    }
+
+    fn empty_document(self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "{}\n",
+            _ => "",
+        }
+    }
+
+    /// Sets the setting at `path`, e.g. `["profiles", "gaming", "delay_ms"]`.
+    fn set_value(self, contents: &str, path: &[&str], value: &Setting) -> String {
+        match self {
+            ConfigFormat::Ini => set_ini_value(contents, path, &value.to_ini()),
+            ConfigFormat::Yaml => set_yaml_value(contents, path, &value.to_yaml()),
+            ConfigFormat::Toml => set_toml_value(contents, path, value),
+            ConfigFormat::Json => set_json_value(contents, path, value),
+        }
+    }
+}
+
+/// The on-disk layout of a config file, shared by every format.
+#[derive(Deserialize)]
+struct ConfigFile {
+    profile: Option<Checked<String>>,
+    #[serde(default)]
+    profiles: BTreeMap<String, ProfileFile>,
+    device_id: Option<Checked<u32>>,
+    device_name: Option<Checked<String>>,
+    toggle_hotkey: Option<Checked<String>>,
+    bypass_key: Option<Checked<String>>,
+    control_socket: Option<Checked<PathBuf>>,
+    control_group: Option<Checked<String>>,
+    dbus_bus: Option<Checked<String>>,
+    metrics_address: Option<Checked<String>>,
+    adaptive_delay: Option<Checked<bool>>,
+    adaptive_min_ms: Option<Checked<u64>>,
+    adaptive_max_ms: Option<Checked<u64>>,
+    correction_key: Option<Checked<String>>,
+    correction_step_ms: Option<Checked<u64>>,
+    learn_keys: Option<Checked<bool>>,
+    chatter_bounces: Option<Checked<usize>>,
+    chatter_window_ms: Option<Checked<u64>>,
+    chatter_factor: Option<Checked<f64>>,
+    chatter_quiet_ms: Option<Checked<u64>>,
+    state_file: Option<Checked<PathBuf>>,
+    /// Top-level settings, inherited by every profile.
+    #[serde(flatten)]
+    base: ProfileFile,
+}
+
+/// Settings that a profile can override.
+#[derive(Deserialize)]
+struct ProfileFile {
+    keys: Option<Checked<KeyList>>,
+    exclude_keys: Option<Checked<KeyList>>,
+    delay_ms: Option<Checked<u64>>,
+    /// `<key>:<ms>` entries, e.g. `KEY_E:120`.
+    key_delay_ms: Option<Checked<KeyList>>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, Value>,
+}
+
+/// INI can only hold a comma separated string, the other formats may use a list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyList {
+    Joined(String),
+    List(Vec<String>),
+}
+
+impl KeyList {
+    fn names(&self) -> Vec<&str> {
+        match self {
+            KeyList::Joined(keys) => keys.split(',').map(str::trim).collect(),
+            KeyList::List(keys) => keys.iter().map(|k| k.trim()).collect(),
+        }
+    }
+}
+
+/// A setting that keeps its deserialization error instead of failing the whole
+/// file, so that every invalid entry can be reported at once.
+enum Checked<T> {
+    Valid(T),
+    Invalid(String),
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Checked<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Ok(match value.try_deserialize::<T>() {
+            Ok(value) => Checked::Valid(value),
+            Err(e) => Checked::Invalid(e.to_string()),
+        })
+    }
+}
+
+impl<T> Checked<T> {
+    fn get(&self, name: &str, errors: &mut Vec<String>) -> Option<&T> {
+        match self {
+            Checked::Valid(value) => Some(value),
+            Checked::Invalid(e) => {
+                report(errors, format!("{name}: {e}"));
+                None
+            }
+        }
+    }
+}
+
+/// Records an error once, even when several profiles inherit the same setting.
+fn report(errors: &mut Vec<String>, error: String) {
+    if !errors.contains(&error) {
+        errors.push(error);
+    }
+}
+
+/// A setting value as written back to the config file.
+#[derive(PartialEq)]
+enum Setting {
+    Number(u64),
+    Text(String),
+    Keys(Vec<String>),
+}
+
+impl Setting {
+    fn to_ini(&self) -> String {
+        match self {
+            Setting::Number(n) => n.to_string(),
+            Setting::Text(text) => text.clone(),
+            Setting::Keys(keys) => keys.join(","),
+        }
+    }
+
+    fn to_yaml(&self) -> String {
+        match self {
+            Setting::Number(n) => n.to_string(),
+            Setting::Text(text) => serde_json::to_string(text).unwrap(),
+            Setting::Keys(keys) => format!("[{}]", keys.join(", ")),
+        }
+    }
+
+    fn to_toml(&self) -> toml_edit::Value {
+        match self {
+            Setting::Number(n) => toml_edit::Value::from(*n as i64),
+            Setting::Text(text) => toml_edit::Value::from(text.as_str()),
+            Setting::Keys(keys) => toml_edit::Value::Array(keys.iter().collect()),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Setting::Number(n) => serde_json::Value::from(*n),
+            Setting::Text(text) => serde_json::Value::from(text.as_str()),
+            Setting::Keys(keys) => serde_json::Value::from(keys.clone()),
+        }
+    }
+}
+
+/// The settings of one resolved profile, with keys identified by the platform's [`Keymap`].
+pub struct ConfigHolder<M: Keymap> {
+    pub profile: String,
+    /// Names of every profile defined next to this one.
+    pub profiles: Vec<String>,
+    pub keys: Vec<M::Key>,
+    pub device_id: u32,
+    pub device_name: String,
+    pub delay_ms: u64,
+    /// Delays of keys that need their own, replacing `delay_ms`.
+    pub key_delay_ms: BTreeMap<M::Key, u64>,
+    pub toggle_hotkey: Vec<M::Key>,
+    pub bypass_key: Option<M::Key>,
+    /// Unix socket the daemon is controlled through.
+    pub control_socket: PathBuf,
+    /// Group allowed to use the control socket, only the owner can when `None`.
+    pub control_group: Option<String>,
+    /// `session`, `system` or the address of the bus to register on, if any.
+    pub dbus_bus: Option<String>,
+    /// Address to serve Prometheus metrics on, if any.
+    pub metrics_address: Option<SocketAddr>,
+    /// Whether to learn the delay of every debounced key, between `adaptive_min_ms` and
+    /// `adaptive_max_ms`.
+    pub adaptive_delay: bool,
+    pub adaptive_min_ms: u64,
+    pub adaptive_max_ms: u64,
+    /// Key whose presses correct mistakes, corrections are not looked for when `None`.
+    pub correction_key: Option<M::Key>,
+    /// How much each correction moves the delay of its key, within `adaptive_min_ms` and
+    /// `adaptive_max_ms`, not at all when zero.
+    pub correction_step_ms: u64,
+    /// Whether to find the keys that bounce among `keys`, and debounce only those once
+    /// enough presses were seen.
+    pub learn_keys: bool,
+    /// Number of suppressed presses of a key within `chatter_window_ms` after which its
+    /// delay is multiplied by `chatter_factor`, until it has had none for
+    /// `chatter_quiet_ms`. Delays are never lengthened when zero.
+    pub chatter_bounces: usize,
+    pub chatter_window_ms: u64,
+    pub chatter_factor: f64,
+    pub chatter_quiet_ms: u64,
+    /// File the daemon keeps what it learned in across restarts.
+    pub state_file: PathBuf,
+    /// Settings as they were loaded, used to write back only what changed.
+    loaded: Vec<(&'static str, Setting)>,
+    /// Settings that live in the profile's own section rather than at the top level.
+    profile_settings: Vec<&'static str>,
+}
+
+impl<M: Keymap> ConfigHolder<M> {
+    /// Returns the settings of the debounce engine.
+    pub fn engine_config(&self) -> EngineConfig<M::Key> {
+        EngineConfig {
+            delay: Duration::from_millis(self.delay_ms),
+            key_delays: self
+                .key_delay_ms
+                .iter()
+                .map(|(&key, &delay_ms)| (key, Duration::from_millis(delay_ms)))
+                .collect(),
+            keys: self.keys.iter().copied().collect(),
+            toggle_hotkey: self.toggle_hotkey.clone(),
+            bypass_key: self.bypass_key,
+            adaptive: self.adaptive_delay.then(|| AdaptiveConfig {
+                floor: Duration::from_millis(self.adaptive_min_ms),
+                ceiling: Duration::from_millis(self.adaptive_max_ms),
+            }),
+            correction: self.correction_key.map(|undo_key| CorrectionConfig {
+                undo_key,
+                step: Duration::from_millis(self.correction_step_ms),
+                floor: Duration::from_millis(self.adaptive_min_ms),
+                ceiling: Duration::from_millis(self.adaptive_max_ms),
+            }),
+            learn_keys: self.learn_keys.then_some(LearningConfig {
+                presses: LEARN_KEYS_PRESSES,
+                bounces: LEARN_KEYS_BOUNCES,
+            }),
+            chatter: (self.chatter_bounces > 0).then(|| ChatterConfig {
+                bounces: self.chatter_bounces,
+                window: Duration::from_millis(self.chatter_window_ms),
+                factor: self.chatter_factor,
+                quiet: Duration::from_millis(self.chatter_quiet_ms),
+            }),
+        }
+    }
+
+    /// Returns the profile after the current one, wrapping around.
+    pub fn next_profile(&self) -> Option<&str> {
+        let current = self.profiles.iter().position(|p| *p == self.profile);
+        let next = current.map_or(0, |i| (i + 1) % self.profiles.len());
+        self.profiles.get(next).map(String::as_str)
+    }
+
+    /// Returns every setting as it is written to the config file.
+    fn settings(&self) -> [(&'static str, Setting); 5] {
+        let mut codes = self.keys.clone();
+        codes.sort_unstable();
+        let keys = codes.into_iter().map(M::name).collect::<Vec<_>>();
+        let key_delay_ms = self
+            .key_delay_ms
+            .iter()
+            .map(|(&key, delay_ms)| format!("{}:{delay_ms}", M::name(key)))
+            .collect();
+        [
+            ("keys", Setting::Keys(keys)),
+            ("delay_ms", Setting::Number(self.delay_ms)),
+            ("key_delay_ms", Setting::Keys(key_delay_ms)),
+            ("device_id", Setting::Number(self.device_id.into())),
+            ("device_name", Setting::Text(self.device_name.clone())),
+        ]
+    }
+}
+
+impl<M: Keymap> Display for ConfigHolder<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let settings = self
+            .settings()
+            .map(|(name, value)| format!("{name}={}", value.to_ini()))
+            .join("\n");
+        write!(f, "{settings}")
+    }
+}
+
+/// Where the configuration is read from. The system file is layered under the
+/// user file, then `DEBOUNCE_*` environment variables and command line overrides
+/// are applied on top, taking precedence over the profiles' own settings too.
+pub struct ConfigSources {
+    pub system: Option<PathBuf>,
+    /// The file settings are written back to.
+    pub user: PathBuf,
+    overrides: Vec<(&'static str, String)>,
+    environment: bool,
+}
+
+impl ConfigSources {
+    /// Uses `config_path` as the user file when given, otherwise looks in
+    /// `$XDG_CONFIG_HOME/debounce-keyboard/` and `/etc/debounce-keyboard/`.
+    pub fn locate(config_path: Option<PathBuf>) -> ConfigSources {
+        let system_dir = Path::new(SYSTEM_CONFIG_DIR);
+        let system = find_config_file(system_dir);
+        let user = config_path.unwrap_or_else(|| match user_config_dir() {
+            Some(dir) => find_config_file(&dir).unwrap_or_else(|| dir.join("config.ini")),
+            None => system
+                .clone()
+                .unwrap_or_else(|| system_dir.join("config.ini")),
+        });
+
+        ConfigSources {
+            system: system.filter(|system| *system != user),
+            user,
+            overrides: Vec::new(),
+            environment: true,
+        }
+    }
+
+    /// Reads a single file, without environment variables.
+    pub fn single_file(path: &Path) -> ConfigSources {
+        ConfigSources {
+            system: None,
+            user: path.to_path_buf(),
+            overrides: Vec::new(),
+            environment: false,
+        }
+    }
+
+    /// Overrides a single setting, taking precedence over every other source.
+    pub fn set_override(&mut self, name: &'static str, value: String) {
+        self.overrides.push((name, value));
+    }
+
+    fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.system.iter().chain([&self.user])
+    }
+}
+
+fn user_config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("debounce-keyboard"))
+}
+
+fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join(format!("config.{extension}")))
+        .find(|path| path.exists())
+}
+
+pub fn load_config<M: Keymap>(sources: &ConfigSources) -> ConfigHolder<M> {
+    let (config, warnings) = read_config(sources, None)
+        .unwrap_or_else(|errors| panic!("invalid config: {}", errors.join("; ")));
+    for warning in warnings {
+        log::warn!("config: {warning}");
+    }
+    config
+}
+
+/// Reads the config again and resolves `profile`, or the configured profile when
+/// `None`, without failing on errors so a running daemon can keep its settings.
+pub fn load_profile<M: Keymap>(
+    sources: &ConfigSources,
+    profile: Option<&str>,
+) -> Result<ConfigHolder<M>, Vec<String>> {
+    read_config(sources, profile).map(|(config, _)| config)
+}
+
+/// Validates every config file without creating any, returning every problem found.
+/// Whether the configured device is connected is left to the platform.
+pub fn check_config<M: Keymap>(sources: &ConfigSources) -> Vec<String> {
+    let files = sources.files().filter(|f| f.exists()).collect::<Vec<_>>();
+    if files.is_empty() {
+        return vec![format!("{}: file does not exist", sources.user.display())];
+    }
+
+    let mut problems = Vec::new();
+    for file in files {
+        let problems_in_file = match read_config::<M>(&ConfigSources::single_file(file), None) {
+            Ok((_, warnings)) => warnings,
+            Err(errors) => errors,
+        };
+        problems.extend(
+            problems_in_file
+                .into_iter()
+                .map(|problem| format!("{}: {problem}", file.display())),
+        );
+    }
+
+    if let Err(errors) = read_config::<M>(sources, None)
+        && problems.is_empty()
+    {
+        problems.extend(
+            errors
+                .into_iter()
+                .map(|error| format!("environment or command line: {error}")),
+        );
+    }
+    problems
+}
+
+/// Parses the layered configuration and resolves `profile`, returning it along with
+/// warnings about entries that were ignored, or every error found followed by those
+/// warnings.
+fn read_config<M: Keymap>(
+    sources: &ConfigSources,
+    profile: Option<&str>,
+) -> Result<(ConfigHolder<M>, Vec<String>), Vec<String>> {
+    let mut builder = Config::builder();
+    for file in sources.files() {
+        let format = ConfigFormat::from_path(file).file_format();
+        builder = builder.add_source(File::new(file.to_str().unwrap(), format).required(false));
+    }
+    let mut forced = Config::builder();
+    if sources.environment {
+        builder = builder.add_source(Environment::with_prefix(ENV_PREFIX));
+        forced = forced.add_source(Environment::with_prefix(ENV_PREFIX));
+    }
+    for (name, value) in &sources.overrides {
+        builder = builder
+            .set_override(*name, value.as_str())
+            .map_err(|e| vec![e.to_string()])?;
+        forced = forced
+            .set_override(*name, value.as_str())
+            .map_err(|e| vec![e.to_string()])?;
+    }
+
+    let settings = builder
+        .build()
+        .and_then(|settings| settings.try_deserialize::<ConfigFile>())
+        .map_err(|e| vec![e.to_string()])?;
+    // Settings given by the environment or the command line beat the profiles' own.
+    let forced = forced
+        .build()
+        .and_then(|forced| forced.try_deserialize::<HashMap<String, Value>>())
+        .map_err(|e| vec![e.to_string()])?
+        .into_keys()
+        .collect::<HashSet<_>>();
+
+    parse_settings(settings, profile, &forced)
+}
+
+fn parse_settings<M: Keymap>(
+    settings: ConfigFile,
+    profile: Option<&str>,
+    forced: &HashSet<String>,
+) -> Result<(ConfigHolder<M>, Vec<String>), Vec<String>> {
+    let mut errors = Vec::new();
+
+    let profiles = [("", &settings.base)]
+        .into_iter()
+        .chain(settings.profiles.iter().map(|(n, p)| (n.as_str(), p)));
+    let warnings = profiles
+        .flat_map(|(profile, file)| {
+            let prefix = profile_prefix(profile);
+            file.unknown
+                .iter()
+                .map(move |(name, value)| match value.kind {
+                    ValueKind::Table(_) => format!("unknown section [{prefix}{name}]"),
+                    _ => format!("unknown setting '{prefix}{name}'"),
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let profile = match profile {
+        Some(profile) => profile.to_owned(),
+        None => settings
+            .profile
+            .as_ref()
+            .and_then(|p| p.get("profile", &mut errors))
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_owned()),
+    };
+    if profile != DEFAULT_PROFILE && !settings.profiles.contains_key(&profile) {
+        errors.push(format!("profile: unknown profile '{profile}'"));
+    }
+
+    // Every profile is validated, not only the active one, so that switching
+    // profiles at runtime cannot fail on a typo.
+    for (name, file) in &settings.profiles {
+        resolve_profile::<M>(&settings.base, Some((name, file)), forced, &mut errors);
+    }
+    let active = settings.profiles.get_key_value(&profile);
+    let (keys, delay_ms, key_delay_ms) = resolve_profile::<M>(
+        &settings.base,
+        active.map(|(n, p)| (n.as_str(), p)),
+        forced,
+        &mut errors,
+    );
+
+    let device_id = settings
+        .device_id
+        .as_ref()
+        .and_then(|v| v.get("device_id", &mut errors))
+        .copied()
+        .unwrap_or(0);
+
+    let device_name = settings
+        .device_name
+        .as_ref()
+        .and_then(|v| v.get("device_name", &mut errors))
+        .cloned()
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    // An empty chord turns the hotkey off, as does leaving it out.
+    let toggle_hotkey = settings
+        .toggle_hotkey
+        .as_ref()
+        .and_then(|v| v.get("toggle_hotkey", &mut errors))
+        .map(|chord| chord.trim())
+        .filter(|chord| !chord.is_empty())
+        .and_then(|chord| {
+            get_chord_codes::<M>(chord)
+                .map_err(|e| errors.push(format!("toggle_hotkey: {e}")))
+                .ok()
+        })
+        .unwrap_or_default();
+
+    let bypass_key = settings
+        .bypass_key
+        .as_ref()
+        .and_then(|v| v.get("bypass_key", &mut errors))
+        .and_then(|key| {
+            let code = M::key(key.trim());
+            if code.is_none() {
+                errors.push(format!("bypass_key: invalid key code {key:?}"));
+            }
+            code
+        });
+
+    let control_socket = settings
+        .control_socket
+        .as_ref()
+        .and_then(|v| v.get("control_socket", &mut errors))
+        .cloned()
+        .unwrap_or_else(default_control_socket);
+
+    let control_group = settings
+        .control_group
+        .as_ref()
+        .and_then(|v| v.get("control_group", &mut errors))
+        .cloned();
+
+    let dbus_bus = settings
+        .dbus_bus
+        .as_ref()
+        .and_then(|v| v.get("dbus_bus", &mut errors))
+        .filter(|bus| {
+            let valid = ["session", "system"].contains(&bus.as_str()) || bus.contains(':');
+            if !valid {
+                errors.push(format!(
+                    "dbus_bus: expected 'session', 'system' or a bus address, got {bus:?}"
+                ));
+            }
+            valid
+        })
+        .cloned();
+
+    let metrics_address = settings
+        .metrics_address
+        .as_ref()
+        .and_then(|v| v.get("metrics_address", &mut errors))
+        .and_then(|address| {
+            address
+                .parse::<SocketAddr>()
+                .map_err(|e| errors.push(format!("metrics_address: {address:?}: {e}")))
+                .ok()
+        });
+
+    let adaptive_delay = settings
+        .adaptive_delay
+        .as_ref()
+        .and_then(|v| v.get("adaptive_delay", &mut errors))
+        .copied()
+        .unwrap_or(false);
+
+    let mut adaptive_ms = |setting: &Option<Checked<u64>>, name: &str, default: u64| match setting
+        .as_ref()
+        .and_then(|v| v.get(name, &mut errors))
+    {
+        Some(&value) => check_delay_ms(value).unwrap_or_else(|e| {
+            errors.push(format!("{name}: {e}"));
+            default
+        }),
+        None => default,
+    };
+    let adaptive_min_ms = adaptive_ms(
+        &settings.adaptive_min_ms,
+        "adaptive_min_ms",
+        DEFAULT_ADAPTIVE_MIN_MS,
+    );
+    let adaptive_max_ms = adaptive_ms(
+        &settings.adaptive_max_ms,
+        "adaptive_max_ms",
+        DEFAULT_ADAPTIVE_MAX_MS,
+    );
+    if adaptive_min_ms > adaptive_max_ms {
+        errors.push(format!(
+            "adaptive_max_ms: {adaptive_max_ms} is below adaptive_min_ms ({adaptive_min_ms})"
+        ));
+    }
+
+    // An empty key turns corrections off, as does a platform without the default one.
+    let correction_key = match settings
+        .correction_key
+        .as_ref()
+        .and_then(|v| v.get("correction_key", &mut errors))
+        .map(|key| key.trim())
+    {
+        Some("") => None,
+        Some(key) => {
+            let code = M::key(key);
+            if code.is_none() {
+                errors.push(format!("correction_key: invalid key code {key:?}"));
+            }
+            code
+        }
+        None => M::key(DEFAULT_CORRECTION_KEY),
+    };
+
+    let correction_step_ms = settings
+        .correction_step_ms
+        .as_ref()
+        .and_then(|v| v.get("correction_step_ms", &mut errors))
+        .map_or(0, |&step_ms| match step_ms {
+            0 => 0,
+            step_ms => check_delay_ms(step_ms).unwrap_or_else(|e| {
+                errors.push(format!("correction_step_ms: {e}"));
+                0
+            }),
+        });
+
+    let learn_keys = settings
+        .learn_keys
+        .as_ref()
+        .and_then(|v| v.get("learn_keys", &mut errors))
+        .copied()
+        .unwrap_or(false);
+
+    let chatter_bounces = settings
+        .chatter_bounces
+        .as_ref()
+        .and_then(|v| v.get("chatter_bounces", &mut errors))
+        .copied()
+        .unwrap_or(0);
+    let mut chatter_ms =
+        |setting: &Option<Checked<u64>>, name: &str, range: RangeInclusive<u64>, default: u64| {
+            match setting.as_ref().and_then(|v| v.get(name, &mut errors)) {
+                Some(&value) => check_range(value, &range).unwrap_or_else(|e| {
+                    errors.push(format!("{name}: {e}"));
+                    default
+                }),
+                None => default,
+            }
+        };
+    let chatter_window_ms = chatter_ms(
+        &settings.chatter_window_ms,
+        "chatter_window_ms",
+        CHATTER_WINDOW_MS_RANGE,
+        DEFAULT_CHATTER_WINDOW_MS,
+    );
+    let chatter_quiet_ms = chatter_ms(
+        &settings.chatter_quiet_ms,
+        "chatter_quiet_ms",
+        CHATTER_QUIET_MS_RANGE,
+        DEFAULT_CHATTER_QUIET_MS,
+    );
+    let chatter_factor = settings
+        .chatter_factor
+        .as_ref()
+        .and_then(|v| v.get("chatter_factor", &mut errors))
+        .map_or(DEFAULT_CHATTER_FACTOR, |&factor| {
+            check_range(factor, &CHATTER_FACTOR_RANGE).unwrap_or_else(|e| {
+                errors.push(format!("chatter_factor: {e}"));
+                DEFAULT_CHATTER_FACTOR
+            })
+        });
+
+    let state_file = settings
+        .state_file
+        .as_ref()
+        .and_then(|v| v.get("state_file", &mut errors))
+        .cloned()
+        .unwrap_or_else(default_state_file);
+
+    if !errors.is_empty() {
+        // Ignored entries are reported along with the errors, as they may well be their
+        // cause.
+        errors.extend(warnings);
+        return Err(errors);
+    }
+
+    let mut profile_settings = Vec::new();
+    if let Some((_, file)) = active {
+        if (file.keys.is_some() && !forced.contains("keys")) || file.exclude_keys.is_some() {
+            profile_settings.push("keys");
+        }
+        if file.delay_ms.is_some() && !forced.contains("delay_ms") {
+            profile_settings.push("delay_ms");
+        }
+        if file.key_delay_ms.is_some() && !forced.contains("key_delay_ms") {
+            profile_settings.push("key_delay_ms");
+        }
+    }
+
+    let mut config = ConfigHolder {
+        profile,
+        profiles: settings.profiles.keys().cloned().collect(),
+        keys,
+        device_id,
+        device_name,
+        delay_ms,
+        key_delay_ms,
+        toggle_hotkey,
+        bypass_key,
+        control_socket,
+        control_group,
+        dbus_bus,
+        metrics_address,
+        adaptive_delay,
+        adaptive_min_ms,
+        adaptive_max_ms,
+        correction_key,
+        correction_step_ms,
+        learn_keys,
+        chatter_bounces,
+        chatter_window_ms,
+        chatter_factor,
+        chatter_quiet_ms,
+        state_file,
+        loaded: Vec::new(),
+        profile_settings,
+    };
+    config.loaded = config.settings().into();
+    Ok((config, warnings))
+}
+
+fn default_control_socket() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| PathBuf::from("/run"), PathBuf::from)
+        .join(CONTROL_SOCKET_NAME)
+}
+
+/// Returns `debounce-keyboard/state.json` in `$XDG_STATE_HOME`, `~/.local/state`, or
+/// `/var/lib` without a home.
+fn default_state_file() -> PathBuf {
+    std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .unwrap_or_else(|| PathBuf::from("/var/lib"))
+        .join(STATE_FILE_PATH)
+}
+
+fn profile_prefix(profile: &str) -> String {
+    match profile {
+        "" => String::new(),
+        profile => format!("profiles.{profile}."),
+    }
+}
+
+/// Picks a profile's own value for a setting, falling back to the top-level one, which
+/// always wins when the setting is in `forced`.
+fn inherit<'a, T>(
+    base: &'a Option<Checked<T>>,
+    profile: Option<(&str, &'a Option<Checked<T>>)>,
+    name: &str,
+    forced: &HashSet<String>,
+) -> (Option<&'a Checked<T>>, String) {
+    match profile {
+        Some((profile, Some(value))) if !forced.contains(name) => {
+            (Some(value), format!("profiles.{profile}.{name}"))
+        }
+        _ => (base.as_ref(), name.to_owned()),
+    }
+}
+
+/// Resolves the keys and delays of a profile layered over the top-level settings.
+fn resolve_profile<M: Keymap>(
+    base: &ProfileFile,
+    profile: Option<(&str, &ProfileFile)>,
+    forced: &HashSet<String>,
+    errors: &mut Vec<String>,
+) -> (Vec<M::Key>, u64, BTreeMap<M::Key, u64>) {
+    let (keys, name) = inherit(
+        &base.keys,
+        profile.map(|(n, p)| (n, &p.keys)),
+        "keys",
+        forced,
+    );
+    let mut keys = match keys {
+        Some(keys) => keys
+            .get(&name, errors)
+            .and_then(|keys| {
+                get_keys_code::<M>(&keys.names())
+                    .map_err(|e| report(errors, format!("{name}: {e}")))
+                    .ok()
+            })
+            .unwrap_or_default(),
+        None => M::all_keys(),
+    };
+
+    let (exclude_keys, name) = inherit(
+        &base.exclude_keys,
+        profile.map(|(n, p)| (n, &p.exclude_keys)),
+        "exclude_keys",
+        forced,
+    );
+    if let Some(exclude_keys) = exclude_keys {
+        let excluded = exclude_keys
+            .get(&name, errors)
+            .and_then(|keys| {
+                get_keys_code::<M>(&keys.names())
+                    .map_err(|e| report(errors, format!("{name}: {e}")))
+                    .ok()
+            })
+            .unwrap_or_default();
+        keys.retain(|key| !excluded.contains(key));
+    }
+
+    let (delay_ms, name) = inherit(
+        &base.delay_ms,
+        profile.map(|(n, p)| (n, &p.delay_ms)),
+        "delay_ms",
+        forced,
+    );
+    let delay_ms = match delay_ms.and_then(|v| v.get(&name, errors)) {
+        Some(&value) => check_delay_ms(value).unwrap_or_else(|e| {
+            report(errors, format!("{name}: {e}"));
+            DEFAULT_DELAY_MS
+        }),
+        None => DEFAULT_DELAY_MS,
+    };
+
+    let (key_delay_ms, name) = inherit(
+        &base.key_delay_ms,
+        profile.map(|(n, p)| (n, &p.key_delay_ms)),
+        "key_delay_ms",
+        forced,
+    );
+    let key_delay_ms = key_delay_ms
+        .and_then(|v| v.get(&name, errors))
+        .and_then(|entries| {
+            get_key_delays::<M>(&entries.names())
+                .map_err(|e| report(errors, format!("{name}: {e}")))
+                .ok()
+        })
+        .unwrap_or_default();
+
+    (keys, delay_ms, key_delay_ms)
+}
+
+/// Checks that a delay is within the supported range.
+pub fn check_delay_ms(delay_ms: u64) -> Result<u64, String> {
+    check_range(delay_ms, &DELAY_MS_RANGE)
+}
+
+fn check_range<T: PartialOrd + Display>(value: T, range: &RangeInclusive<T>) -> Result<T, String> {
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!(
+            "{value} is out of range ({}-{})",
+            range.start(),
+            range.end()
+        ))
+    }
+}
+
+/// Writes the settings that changed since the config was loaded, in the file's own
+/// format, keeping comments, ordering and any other entries untouched.
+pub fn save_config_to_path<M: Keymap>(path: &PathBuf, config: &ConfigHolder<M>) {
+    let format = ConfigFormat::from_path(path);
+    let mut contents =
+        std::fs::read_to_string(path).unwrap_or_else(|_| format.empty_document().to_owned());
+
+    let mut changed = false;
+    for ((name, value), (_, loaded)) in config.settings().into_iter().zip(&config.loaded) {
+        if value == *loaded {
+            continue;
+        }
+        contents = if config.profile_settings.contains(&name) {
+            format.set_value(&contents, &["profiles", &config.profile, name], &value)
+        } else {
+            format.set_value(&contents, &[name], &value)
+        };
+        changed = true;
+    }
+
+    if changed {
+        if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).expect("Failed to create config directory");
+        }
+        write_atomically(path, contents.as_bytes()).expect("Failed to write to config file");
+    }
+}
+
+/// Replaces a setting in an INI document, where every element of `path` but the
+/// last names the section (`[profiles.gaming]`). Missing settings are appended to
+/// their section, and missing sections to the document.
+fn set_ini_value(contents: &str, path: &[&str], value: &str) -> String {
+    let (name, sections) = path.split_last().unwrap();
+    let mut lines = contents.lines().map(str::to_owned).collect::<Vec<_>>();
+    let is_header = |l: &String| l.trim_start().starts_with('[');
+
+    let (start, end) = if sections.is_empty() {
+        (0, lines.iter().position(is_header).unwrap_or(lines.len()))
+    } else {
+        let header = format!("[{}]", sections.join("."));
+        match lines.iter().position(|l| l.trim() == header) {
+            Some(i) => {
+                let end = lines[i + 1..]
+                    .iter()
+                    .position(is_header)
+                    .map_or(lines.len(), |n| i + 1 + n);
+                (i + 1, end)
+            }
+            None => {
+                if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push(header);
+                (lines.len(), lines.len())
+            }
+        }
+    };
+
+    let existing = lines[start..end].iter().position(|l| {
+        l.split_once(['=', ':'])
+            .is_some_and(|(k, _)| k.trim() == *name)
+    });
+    match existing {
+        Some(i) => lines[start + i] = format!("{name}={value}"),
+        None => {
+            let insert_at = lines[start..end]
+                .iter()
+                .rposition(|l| !l.trim().is_empty())
+                .map_or(start, |i| start + i + 1);
+            lines.insert(insert_at, format!("{name}={value}"));
+        }
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Replaces a YAML mapping entry, including any block below it, with a single-line
+/// flow value. Missing entries and parent mappings are appended.
+fn set_yaml_value(contents: &str, path: &[&str], value: &str) -> String {
+    fn indent_of(line: &str) -> usize {
+        line.len() - line.trim_start().len()
+    }
+    fn is_content(line: &str) -> bool {
+        !line.trim().is_empty() && !line.trim_start().starts_with('#')
+    }
+    fn key_of(line: &str) -> Option<&str> {
+        let (key, _) = line.trim_start().split_once(':')?;
+        Some(key.trim().trim_matches(['"', '\'']))
+    }
+
+    let mut lines = contents.lines().map(str::to_owned).collect::<Vec<_>>();
+    let (mut start, mut end, mut indent) = (0, lines.len(), 0);
+
+    for (depth, key) in path.iter().enumerate() {
+        let is_last = depth == path.len() - 1;
+        let existing = (start..end).find(|&i| {
+            is_content(&lines[i])
+                && indent_of(&lines[i]) == indent
+                && !lines[i].trim_start().starts_with('-')
+                && key_of(&lines[i]) == Some(key)
+        });
+
+        let Some(i) = existing else {
+            let insert_at = (start..end)
+                .rev()
+                .find(|&i| is_content(&lines[i]))
+                .map_or(start, |i| i + 1);
+            let missing = path[depth..].iter().enumerate().map(|(n, key)| {
+                let pad = " ".repeat(indent + 2 * n);
+                if depth + n == path.len() - 1 {
+                    format!("{pad}{key}: {value}")
+                } else {
+                    format!("{pad}{key}:")
+                }
+            });
+            lines.splice(insert_at..insert_at, missing.collect::<Vec<_>>());
+            break;
+        };
+
+        // A block ends at the next entry at the same or lower indentation; block
+        // sequences may start at the same indentation as their key.
+        let block_end = (i + 1..end)
+            .find(|&j| {
+                is_content(&lines[j])
+                    && (indent_of(&lines[j]) < indent
+                        || (indent_of(&lines[j]) == indent
+                            && !lines[j].trim_start().starts_with('-')))
+            })
+            .unwrap_or(end);
+
+        if is_last {
+            let pad = " ".repeat(indent);
+            let comment = trailing_comment(&lines[i]).unwrap_or_default();
+            lines.splice(i..block_end, [format!("{pad}{key}: {value}{comment}")]);
+            break;
+        }
+
+        let child_indent = (i + 1..block_end)
+            .find(|&j| is_content(&lines[j]))
+            .map_or(indent + 2, |j| indent_of(&lines[j]));
+        (start, end, indent) = (i + 1, block_end, child_indent);
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Returns the comment ending a YAML line, along with the spaces before it.
+fn trailing_comment(line: &str) -> Option<&str> {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '#') if previous.is_whitespace() => {
+                let start = line[..i].trim_end().len();
+                return Some(&line[start..]);
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+fn set_toml_value(contents: &str, path: &[&str], value: &Setting) -> String {
+    let (name, parents) = path.split_last().unwrap();
+    let mut document = contents
+        .parse::<toml_edit::DocumentMut>()
+        .expect("Failed to parse config file");
+
+    let mut table = document.as_table_mut();
+    for parent in parents {
+        table = table
+            .entry(parent)
+            .or_insert_with(|| {
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            })
+            .as_table_mut()
+            .expect("Config file section is not a table");
+    }
+
+    match table.get_mut(name).and_then(|item| item.as_value_mut()) {
+        Some(current) => {
+            let decor = current.decor().clone();
+            *current = value.to_toml();
+            *current.decor_mut() = decor;
+        }
+        None => {
+            table.insert(name, toml_edit::Item::Value(value.to_toml()));
+        }
+    }
+    document.to_string()
+}
+
+fn set_json_value(contents: &str, path: &[&str], value: &Setting) -> String {
+    let (name, parents) = path.split_last().unwrap();
+    let mut document =
+        serde_json::from_str::<serde_json::Value>(contents).expect("Failed to parse config file");
+
+    let mut object = document
+        .as_object_mut()
+        .expect("Config file must contain a JSON object");
+    for parent in parents {
+        object = object
+            .entry(*parent)
+            .or_insert_with(|| serde_json::Value::Object(Default::default()))
+            .as_object_mut()
+            .expect("Config file section is not an object");
+    }
+    object.insert((*name).to_owned(), value.to_json());
+
+    let mut contents = serde_json::to_string_pretty(&document).unwrap();
+    contents.push('\n');
+    contents
+}
+
+/// Writes to a temporary file next to `path` and renames it over the original, so
+/// an interrupted write never leaves a truncated config behind. When `path` is a
+/// symlink, the file it points to is replaced instead.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let path = &std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{file_name}.tmp"));
+
+    let mut temp_file = std::fs::File::create(&temp_path)?;
+    temp_file.write_all(contents)?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        temp_file.set_permissions(metadata.permissions())?;
+    }
+    temp_file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+
+    if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Returns the group a key belongs to, e.g. `@letters`, or `@other`.
+pub fn key_class<M: Keymap>(key: M::Key) -> &'static str {
+    KEY_GROUPS
+        .iter()
+        .find(|(_, names)| names.iter().any(|name| M::key(name) == Some(key)))
+        .map_or("@other", |&(group, _)| group)
+}
+
+/// Resolves a chord such as `KEY_LEFTCTRL+KEY_LEFTALT+KEY_PAUSE` to its keys.
+fn get_chord_codes<M: Keymap>(chord: &str) -> Result<Vec<M::Key>, String> {
+    let keys = chord.split('+').map(str::trim).collect::<Vec<_>>();
+    let invalid_keys = keys
+        .iter()
+        .filter(|key| M::key(key).is_none())
+        .copied()
+        .collect::<Vec<_>>();
+    if !invalid_keys.is_empty() {
+        return Err(format!("invalid key codes: {:?}", invalid_keys.join("+")));
+    }
+    Ok(keys.into_iter().filter_map(M::key).collect())
+}
+
+/// Resolves key names, numeric codes and `@group` names to keys, without duplicates.
+/// Keys of a group that the platform does not have are left out.
+pub fn get_keys_code<M: Keymap>(keys: &[&str]) -> Result<Vec<M::Key>, String> {
+    let key_groups_map = KEY_GROUPS.into_iter().collect::<HashMap<&str, &[&str]>>();
+
+    let mut keys_code = Vec::new();
+    let mut invalid_keys = Vec::new();
+    for &key in keys {
+        let codes = match (M::key(key), key_groups_map.get(key)) {
+            (Some(code), _) => vec![code],
+            (None, Some(group)) => group.iter().filter_map(|name| M::key(name)).collect(),
+            (None, None) => {
+                invalid_keys.push(key);
+                continue;
+            }
+        };
+        for code in codes {
+            if !keys_code.contains(&code) {
+                keys_code.push(code);
+            }
+        }
+    }
+
+    if invalid_keys.is_empty() {
+        Ok(keys_code)
+    } else {
+        Err(format!("invalid key codes: {:?}", invalid_keys.join(",")))
+    }
+}
+
+/// Parses `<key>:<ms>` entries, reporting every invalid one.
+fn get_key_delays<M: Keymap>(entries: &[&str]) -> Result<BTreeMap<M::Key, u64>, String> {
+    let mut delays = BTreeMap::new();
+    let mut invalid = Vec::new();
+    for entry in entries.iter().filter(|entry| !entry.is_empty()) {
+        let delay = entry.rsplit_once(':').and_then(|(key, delay_ms)| {
+            let key = M::key(key.trim())?;
+            let delay_ms = delay_ms.trim().parse::<u64>().ok()?;
+            Some((key, check_delay_ms(delay_ms).ok()?))
+        });
+        match delay {
+            Some((key, delay_ms)) => {
+                delays.insert(key, delay_ms);
+            }
+            None => invalid.push(*entry),
+        }
+    }
+    if invalid.is_empty() {
+        Ok(delays)
+    } else {
+        Err(format!(
+            "invalid key delays: {:?}, expected <key>:<ms> with ms in {}-{}",
+            invalid.join(","),
+            DELAY_MS_RANGE.start(),
+            DELAY_MS_RANGE.end()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Numbers the keys of every group, in order.
+    struct TestKeys;
+
+    impl Keymap for TestKeys {
+        type Key = u16;
+
+        fn key(name: &str) -> Option<u16> {
+            let names = KEY_GROUPS.iter().flat_map(|(_, names)| names.iter());
+            let position = names.clone().position(|&n| n == name);
+            position
+                .map(|i| i as u16)
+                .or_else(|| u16::from_str_radix(name.strip_prefix("0x")?, 16).ok())
+        }
+
+        fn name(key: u16) -> String {
+            let mut names = KEY_GROUPS.iter().flat_map(|(_, names)| names.iter());
+            names
+                .nth(key.into())
+                .map_or_else(|| format!("{key:#x}"), |name| name.to_string())
+        }
+
+        fn all_keys() -> Vec<u16> {
+            (0..KEY_GROUPS.iter().map(|(_, names)| names.len() as u16).sum()).collect()
+        }
+    }
+
+    fn key(name: &str) -> u16 {
+        TestKeys::key(name).unwrap()
+    }
+
+    /// Writes `contents` to a file of its own and returns its path.
+    fn write_file(name: &str, contents: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("debounce-keyboard-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn read(
+        name: &str,
+        contents: &str,
+    ) -> Result<(ConfigHolder<TestKeys>, Vec<String>), Vec<String>> {
+        read_config(
+            &ConfigSources::single_file(&write_file(name, contents)),
+            None,
+        )
+    }
+
+    #[test]
+    fn reads_every_format_alike() {
+        let files = [
+            ("same.ini", "keys=KEY_A,KEY_B\ndelay_ms=40\n"),
+            (
+                "same.toml",
+                "keys = [\"KEY_A\", \"KEY_B\"]\ndelay_ms = 40\n",
+            ),
+            ("same.yaml", "keys:\n  - KEY_A\n  - KEY_B\ndelay_ms: 40\n"),
+            (
+                "same.json",
+                "{\"keys\": \"KEY_A, KEY_B\", \"delay_ms\": 40}\n",
+            ),
+        ];
+        for (name, contents) in files {
+            let (config, warnings) = read(name, contents).unwrap();
+            assert_eq!(config.keys, [key("KEY_A"), key("KEY_B")], "{name}");
+            assert_eq!(config.delay_ms, 40, "{name}");
+            assert_eq!(config.profile, DEFAULT_PROFILE, "{name}");
+            assert!(warnings.is_empty(), "{name}: {warnings:?}");
+        }
+    }
+
+    #[test]
+    fn resolves_groups_without_duplicates() {
+        let keys = get_keys_code::<TestKeys>(&["KEY_A", "@letters", "0xc8"]).unwrap();
+        assert_eq!(keys.len(), 27);
+        assert_eq!(keys[0], key("KEY_A"));
+        assert_eq!(keys[26], 200);
+        assert_eq!(
+            get_keys_code::<TestKeys>(&["KEY_A", "KEY_FOO", "@nope"]),
+            Err("invalid key codes: \"KEY_FOO,@nope\"".to_owned())
+        );
+        assert_eq!(key_class::<TestKeys>(key("KEY_KP1")), "@numpad");
+        assert_eq!(key_class::<TestKeys>(200), "@other");
+    }
+
+    #[test]
+    fn profiles_inherit_top_level_settings() {
+        let contents = "keys=@digits\ndelay_ms=60\nprofile=gaming\n\
+                        [profiles.gaming]\nexclude_keys=KEY_1\n\
+                        [profiles.typing]\ndelay_ms=90\n";
+        let (config, _) = read("profiles.ini", contents).unwrap();
+        assert_eq!(config.profile, "gaming");
+        assert_eq!(config.profiles, ["gaming", "typing"]);
+        assert_eq!(config.keys.len(), 9);
+        assert!(!config.keys.contains(&key("KEY_1")));
+        assert_eq!(config.delay_ms, 60);
+        assert_eq!(config.next_profile(), Some("typing"));
+
+        let sources = ConfigSources::single_file(&write_file("profiles.ini", contents));
+        let config = load_profile::<TestKeys>(&sources, Some("typing")).unwrap();
+        assert_eq!((config.keys.len(), config.delay_ms), (10, 90));
+        assert_eq!(config.next_profile(), Some("gaming"));
+    }
+
+    #[test]
+    fn overrides_beat_profile_settings() {
+        let contents = "profile=gaming\ndelay_ms=85\n[profiles.gaming]\ndelay_ms=40\nkeys=KEY_W\n";
+        let mut sources = ConfigSources::single_file(&write_file("overrides.ini", contents));
+        sources.set_override("delay_ms", "100".to_owned());
+        sources.set_override("keys", "KEY_A".to_owned());
+        let (config, _) = read_config::<TestKeys>(&sources, None).unwrap();
+        assert_eq!(config.profile, "gaming");
+        assert_eq!(config.delay_ms, 100);
+        assert_eq!(config.keys, [key("KEY_A")]);
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let contents = "keys=KEY_A,KEY_FOO\ndelay_ms=5000\nbogus=1\nprofile=nope\n\
+                        [weird]\nx=1\n[profiles.x]\ndelay_ms=abc\n";
+        let errors = read("problems.ini", contents).err().unwrap();
+        assert_eq!(
+            errors,
+            [
+                "profile: unknown profile 'nope'",
+                "keys: invalid key codes: \"KEY_FOO\"",
+                "profiles.x.delay_ms: invalid type: string \"abc\", expected an integer",
+                "delay_ms: 5000 is out of range (1-1000)",
+                "unknown setting 'bogus'",
+                "unknown section [weird]",
+            ]
+        );
+    }
+
+    #[test]
+    fn key_delays_are_saved_and_read_back_in_every_format() {
+        for name in ["delays.ini", "delays.yaml", "delays.toml", "delays.json"] {
+            let format = ConfigFormat::from_path(Path::new(name));
+            let path = write_file(name, format.empty_document());
+            let sources = ConfigSources::single_file(&path);
+            let mut config = load_profile::<TestKeys>(&sources, None).unwrap();
+            assert!(config.key_delay_ms.is_empty(), "{name}");
+
+            config.key_delay_ms = [(key("KEY_E"), 120), (key("KEY_T"), 30)].into();
+            save_config_to_path(&path, &config);
+            let config = load_profile::<TestKeys>(&sources, None).unwrap();
+            let contents = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(
+                config.key_delay_ms,
+                [(key("KEY_E"), 120), (key("KEY_T"), 30)].into(),
+                "{name}:\n{contents}"
+            );
+            assert_eq!(
+                config.engine_config().delay_of(&key("KEY_E")),
+                Duration::from_millis(120)
+            );
+        }
+
+        let errors = read(
+            "delays.ini",
+            "key_delay_ms=KEY_E:120,KEY_T,KEY_FOO:5,KEY_A:0\n",
+        );
+        assert_eq!(
+            errors.err().unwrap(),
+            [
+                "key_delay_ms: invalid key delays: \"KEY_T,KEY_FOO:5,KEY_A:0\", \
+              expected <key>:<ms> with ms in 1-1000"
+            ]
+        );
+    }
+
+    #[test]
+    fn adaptive_delays_are_bounded_by_valid_delays() {
+        let (config, _) =
+            read("adaptive.ini", "adaptive_delay=true\nadaptive_max_ms=90\n").unwrap();
+        assert_eq!(
+            config.engine_config().adaptive,
+            Some(AdaptiveConfig {
+                floor: Duration::from_millis(DEFAULT_ADAPTIVE_MIN_MS),
+                ceiling: Duration::from_millis(90),
+            })
+        );
+        let (config, _) = read("adaptive.ini", "adaptive_max_ms=90\n").unwrap();
+        assert_eq!(config.engine_config().adaptive, None);
+
+        let errors = read(
+            "adaptive.ini",
+            "adaptive_delay=yes please\nadaptive_min_ms=0\nadaptive_max_ms=5\n",
+        );
+        assert_eq!(
+            errors.err().unwrap(),
+            [
+                "adaptive_delay: invalid type: string \"yes please\", expected a boolean",
+                "adaptive_min_ms: 0 is out of range (1-1000)",
+                "adaptive_max_ms: 5 is below adaptive_min_ms (10)",
+            ]
+        );
+    }
+
+    #[test]
+    fn corrections_are_looked_for_unless_turned_off() {
+        let (config, _) = read("corrections.ini", "correction_key=KEY_DELETE\n").unwrap();
+        assert_eq!(
+            config.engine_config().correction,
+            Some(CorrectionConfig {
+                undo_key: key("KEY_DELETE"),
+                step: Duration::ZERO,
+                floor: Duration::from_millis(DEFAULT_ADAPTIVE_MIN_MS),
+                ceiling: Duration::from_millis(DEFAULT_ADAPTIVE_MAX_MS),
+            })
+        );
+        let (config, _) = read("corrections.ini", "correction_key=\n").unwrap();
+        assert_eq!(config.engine_config().correction, None);
+
+        let errors = read(
+            "corrections.ini",
+            "correction_key=KEY_FOO\ncorrection_step_ms=2000\n",
+        );
+        assert_eq!(
+            errors.err().unwrap(),
+            [
+                "correction_key: invalid key code \"KEY_FOO\"",
+                "correction_step_ms: 2000 is out of range (1-1000)",
+            ]
+        );
+    }
+
+    #[test]
+    fn toggle_hotkey_is_off_when_empty() {
+        let (config, _) = read("hotkey.ini", "toggle_hotkey=KEY_LEFTCTRL + KEY_LEFTALT\n").unwrap();
+        assert_eq!(
+            config.toggle_hotkey,
+            [key("KEY_LEFTCTRL"), key("KEY_LEFTALT")]
+        );
+        let (config, _) = read("hotkey.ini", "toggle_hotkey=\n").unwrap();
+        assert!(config.toggle_hotkey.is_empty());
+        let (config, _) = read("hotkey.ini", "").unwrap();
+        assert!(config.toggle_hotkey.is_empty());
+
+        let errors = read("hotkey.ini", "toggle_hotkey=KEY_LEFTCTRL+\n");
+        assert_eq!(
+            errors.err().unwrap(),
+            ["toggle_hotkey: invalid key codes: \"\""]
+        );
+    }
+
+    #[test]
+    fn learning_keys_watches_presses_of_every_debounced_key() {
+        let (config, _) = read("learn.ini", "learn_keys=true\n").unwrap();
+        assert_eq!(
+            config.engine_config().learn_keys,
+            Some(LearningConfig {
+                presses: LEARN_KEYS_PRESSES,
+                bounces: LEARN_KEYS_BOUNCES,
+            })
+        );
+        let (config, _) = read("learn.ini", "").unwrap();
+        assert_eq!(config.engine_config().learn_keys, None);
+    }
+
+    #[test]
+    fn chatter_settings_are_checked() {
+        let (config, _) = read(
+            "chatter.ini",
+            "chatter_bounces=3\nchatter_factor=1.5\nchatter_quiet_ms=5000\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.engine_config().chatter,
+            Some(ChatterConfig {
+                bounces: 3,
+                window: Duration::from_millis(DEFAULT_CHATTER_WINDOW_MS),
+                factor: 1.5,
+                quiet: Duration::from_millis(5000),
+            })
+        );
+        let (config, _) = read("chatter.ini", "chatter_factor=3\n").unwrap();
+        assert_eq!(config.engine_config().chatter, None);
+
+        let errors = read(
+            "chatter.ini",
+            "chatter_bounces=3\nchatter_window_ms=0\nchatter_factor=0.5\n",
+        );
+        assert_eq!(
+            errors.err().unwrap(),
+            [
+                "chatter_window_ms: 0 is out of range (1-60000)",
+                "chatter_factor: 0.5 is out of range (1-10)",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_config_reports_warnings_of_valid_files() {
+        let path = write_file("warnings.ini", "delay_ms=40\nDelay_MS=50\n");
+        let problems = check_config::<TestKeys>(&ConfigSources::single_file(&path));
+        assert_eq!(
+            problems,
+            [format!("{}: unknown setting 'Delay_MS'", path.display())]
+        );
+
+        let path = write_file("missing.ini", "");
+        std::fs::remove_file(&path).unwrap();
+        let problems = check_config::<TestKeys>(&ConfigSources::single_file(&path));
+        assert_eq!(
+            problems,
+            [format!("{}: file does not exist", path.display())]
+        );
+    }
+
+    /// Sets top-level and profile settings in a document, then reads it back with the
+    /// profile it selects and with the default one.
+    fn edit_and_read(name: &str, contents: &str) -> (String, [ConfigHolder<TestKeys>; 2]) {
+        let format = ConfigFormat::from_path(Path::new(name));
+        let edits = [
+            (&["delay_ms"][..], Setting::Number(70)),
+            (&["profiles", "gaming", "delay_ms"], Setting::Number(30)),
+            (
+                &["keys"],
+                Setting::Keys(vec!["KEY_A".to_owned(), "KEY_B".to_owned()]),
+            ),
+            (&["device_name"], Setting::Text("Board \"#2\"".to_owned())),
+        ];
+        let contents = edits
+            .iter()
+            .fold(contents.to_owned(), |contents, (path, value)| {
+                format.set_value(&contents, path, value)
+            });
+        let sources = ConfigSources::single_file(&write_file(name, &contents));
+        let configs = [None, Some(DEFAULT_PROFILE)]
+            .map(|profile| load_profile::<TestKeys>(&sources, profile).unwrap());
+        (contents, configs)
+    }
+
+    #[test]
+    fn editors_round_trip_keeping_comments() {
+        let documents = [
+            (
+                "edit.ini",
+                "# top\ndelay_ms=40\nkeys=KEY_Z\nprofile=gaming\n\n\
+                 [profiles.gaming]\n; gaming\ndelay_ms=20\n",
+                &["# top", "; gaming"][..],
+            ),
+            (
+                "edit.yaml",
+                "# top\ndelay_ms: 40 # inline\nkeys:\n  - KEY_Z\nprofile: gaming\n\
+                 profiles:\n  gaming:\n    delay_ms: 20 # fast\n",
+                &["# top", "delay_ms: 70 # inline", "delay_ms: 30 # fast"],
+            ),
+            (
+                "edit.toml",
+                "# top\ndelay_ms = 40 # inline\nkeys = [\"KEY_Z\"]\nprofile = \"gaming\"\n\n\
+                 [profiles.gaming]\ndelay_ms = 20 # fast\n",
+                &["# top", "delay_ms = 70 # inline", "delay_ms = 30 # fast"],
+            ),
+            (
+                "edit.json",
+                "{\"delay_ms\": 40, \"keys\": [\"KEY_Z\"], \"profile\": \"gaming\",\n\
+                 \"profiles\": {\"gaming\": {\"delay_ms\": 20}}}\n",
+                &["{\n  \"delay_ms\": 70,\n  \"keys\""],
+            ),
+        ];
+        for (name, contents, kept) in documents {
+            let (contents, [gaming, default]) = edit_and_read(name, contents);
+            for kept in kept {
+                assert!(
+                    contents.contains(kept),
+                    "{name}: {kept:?} lost in\n{contents}"
+                );
+            }
+            assert_eq!(gaming.profile, "gaming", "{name}");
+            assert_eq!(gaming.delay_ms, 30, "{name}");
+            assert_eq!(gaming.keys, [key("KEY_A"), key("KEY_B")], "{name}");
+            assert_eq!(gaming.device_name, "Board \"#2\"", "{name}");
+            assert_eq!(default.delay_ms, 70, "{name}");
+        }
+    }
+
+    #[test]
+    fn editors_create_missing_sections() {
+        for name in ["empty.ini", "empty.yaml", "empty.toml", "empty.json"] {
+            let format = ConfigFormat::from_path(Path::new(name));
+            let contents = format.set_value(
+                format.empty_document(),
+                &["profiles", "new", "delay_ms"],
+                &Setting::Number(25),
+            );
+            let sources = ConfigSources::single_file(&write_file(name, &contents));
+            let config = load_profile::<TestKeys>(&sources, Some("new")).unwrap();
+            assert_eq!(config.delay_ms, 25, "{name}:\n{contents}");
+        }
+    }
+
+    #[test]
+    fn finds_yaml_trailing_comments() {
+        assert_eq!(
+            trailing_comment("delay_ms: 40  # inline"),
+            Some("  # inline")
+        );
+        assert_eq!(trailing_comment("name: \"a # b\""), None);
+        assert_eq!(trailing_comment("name: 'a # b' # c"), Some(" # c"));
+        assert_eq!(trailing_comment("name: a#b"), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn write_atomically_follows_symlinks() {
+        let target = write_file("target.ini", "delay_ms=40\n");
+        let link = target.with_file_name("link.ini");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_atomically(&link, b"delay_ms=50\n").unwrap();
+        assert!(std::fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "delay_ms=50\n");
+    }
+}
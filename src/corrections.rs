@@ -0,0 +1,235 @@
+//! Telling from the user's corrections where debouncing got it wrong.
+//!
+//! Two patterns give it away. A key pressed twice, close enough to be one press that
+//! bounced, and then undone with Backspace, is a bounce that was let through. A press that
+//! was suppressed although the key had been released for a while, and that is retyped
+//! right away, was a deliberate press eaten by the delay. [`CorrectionDetector`] looks for
+//! both in the events and what was decided for them.
+
+use crate::calibration::Timings;
+use crate::engine::{KeyEvent, PRESS, Timestamp};
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Longest time between a mistake and its correction.
+pub const CORRECTION_WINDOW: Duration = Duration::from_secs(1);
+
+/// Settings of correction detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorrectionConfig<K> {
+    /// Key that undoes the last character typed, such as Backspace.
+    pub undo_key: K,
+    /// How much every correction moves the delay of its key, not at all when zero.
+    pub step: Duration,
+    /// Shortest delay corrections move a delay down to.
+    pub floor: Duration,
+    /// Longest delay corrections move a delay up to.
+    pub ceiling: Duration,
+}
+
+/// What a correction revealed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorrectionKind {
+    /// A bounce was let through, and its character deleted.
+    MissedBounce,
+    /// A deliberate press was suppressed, and typed again.
+    FalsePositive,
+}
+
+/// A correction of a key's mistake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Correction<K> {
+    pub key: K,
+    pub kind: CorrectionKind,
+}
+
+/// A press along with what was decided for it.
+#[derive(Debug, Clone, Copy)]
+struct DecidedPress<K, T> {
+    key: K,
+    timestamp: T,
+    passed: bool,
+    /// Whether it came after the previous press of the same key and within twice the
+    /// delay, close enough for both to be one bounced press.
+    close: bool,
+    /// Whether it came so soon after the key was released that it was a bounce.
+    bounce: bool,
+}
+
+/// Looks for corrections in events as they are decided.
+#[derive(Debug)]
+pub struct CorrectionDetector<K, T> {
+    undo_key: K,
+    /// Only how the latest press of every key compares to the one before is needed.
+    timings: Timings<K, T>,
+    /// The last two presses decided, the latest last.
+    presses: [Option<DecidedPress<K, T>>; 2],
+}
+
+impl<K, T> CorrectionDetector<K, T>
+where
+    K: Copy + Eq + Hash + Ord,
+    T: Timestamp,
+{
+    pub fn new(undo_key: K) -> CorrectionDetector<K, T> {
+        CorrectionDetector {
+            undo_key,
+            timings: Timings::rolling(1),
+            presses: [None, None],
+        }
+    }
+
+    /// Records an event along with whether it was passed and the delay of its key,
+    /// returning the correction it makes, if any. Events have to be given in the order they
+    /// happened.
+    pub fn observe(
+        &mut self,
+        event: &KeyEvent<K, T>,
+        passed: bool,
+        delay: Duration,
+    ) -> Option<Correction<K>> {
+        let measured = self.timings.observe(event);
+        if event.value != PRESS {
+            return None;
+        }
+        let press = DecidedPress {
+            key: event.code,
+            timestamp: event.timestamp,
+            passed,
+            close: measured.is_some_and(|press| press.interval <= delay * 2),
+            bounce: measured.is_some_and(|press| press.bounce),
+        };
+        let soon_after = |earlier: &DecidedPress<K, T>| {
+            event
+                .timestamp
+                .checked_duration_since(earlier.timestamp)
+                .is_some_and(|elapsed| elapsed <= CORRECTION_WINDOW)
+        };
+
+        let correction = match self.presses {
+            [Some(first), Some(second)]
+                if event.code == self.undo_key
+                    && passed
+                    && first.key == second.key
+                    && first.passed
+                    && second.passed
+                    && second.close
+                    && soon_after(&second) =>
+            {
+                Some(Correction {
+                    key: second.key,
+                    kind: CorrectionKind::MissedBounce,
+                })
+            }
+            [_, Some(last)]
+                if last.key == event.code
+                    && passed
+                    && !last.passed
+                    && !last.bounce
+                    && soon_after(&last) =>
+            {
+                Some(Correction {
+                    key: last.key,
+                    kind: CorrectionKind::FalsePositive,
+                })
+            }
+            _ => None,
+        };
+        // A correction is counted once, however many times it is repeated.
+        self.presses = match correction {
+            Some(_) => [None, None],
+            None => [self.presses[1], Some(press)],
+        };
+        correction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::RELEASE;
+
+    const KEY_A: u16 = 30;
+    const KEY_S: u16 = 31;
+    const KEY_BACKSPACE: u16 = 14;
+
+    const DELAY: Duration = Duration::from_millis(50);
+
+    /// Feeds `(code, value, milliseconds, passed)` events and returns the corrections.
+    fn corrections(events: &[(u16, i32, u64, bool)]) -> Vec<Correction<u16>> {
+        let mut detector = CorrectionDetector::new(KEY_BACKSPACE);
+        events
+            .iter()
+            .filter_map(|&(code, value, ms, passed)| {
+                let event = KeyEvent::new(code, value, Duration::from_millis(ms));
+                detector.observe(&event, passed, DELAY)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deleted_doubles_are_missed_bounces() {
+        let events = [
+            (KEY_A, PRESS, 0, true),
+            (KEY_A, RELEASE, 5, true),
+            (KEY_A, PRESS, 60, true),
+            (KEY_A, RELEASE, 65, true),
+            (KEY_BACKSPACE, PRESS, 400, true),
+            (KEY_BACKSPACE, RELEASE, 450, true),
+            (KEY_BACKSPACE, PRESS, 500, true),
+        ];
+        assert_eq!(
+            corrections(&events),
+            [Correction {
+                key: KEY_A,
+                kind: CorrectionKind::MissedBounce
+            }]
+        );
+
+        // Doubles typed on purpose, or deleted long after, are not.
+        let events = [
+            (KEY_A, PRESS, 0, true),
+            (KEY_A, RELEASE, 50, true),
+            (KEY_A, PRESS, 200, true),
+            (KEY_BACKSPACE, PRESS, 300, true),
+            (KEY_S, PRESS, 1000, true),
+            (KEY_S, RELEASE, 1005, true),
+            (KEY_S, PRESS, 1060, true),
+            (KEY_BACKSPACE, PRESS, 2100, true),
+        ];
+        assert_eq!(corrections(&events), []);
+    }
+
+    #[test]
+    fn retyped_suppressions_are_false_positives() {
+        let events = [
+            (KEY_A, PRESS, 0, true),
+            (KEY_A, RELEASE, 10, true),
+            (KEY_A, PRESS, 40, false),
+            (KEY_A, RELEASE, 50, false),
+            (KEY_A, PRESS, 300, true),
+        ];
+        assert_eq!(
+            corrections(&events),
+            [Correction {
+                key: KEY_A,
+                kind: CorrectionKind::FalsePositive
+            }]
+        );
+
+        // Bounces suppressed before a deliberate press, or presses followed by another
+        // key, are not.
+        let events = [
+            (KEY_A, PRESS, 0, true),
+            (KEY_A, RELEASE, 3, true),
+            (KEY_A, PRESS, 5, false),
+            (KEY_A, RELEASE, 8, false),
+            (KEY_A, PRESS, 150, true),
+            (KEY_A, RELEASE, 160, true),
+            (KEY_A, PRESS, 190, false),
+            (KEY_S, PRESS, 250, true),
+            (KEY_A, PRESS, 300, true),
+        ];
+        assert_eq!(corrections(&events), []);
+    }
+}
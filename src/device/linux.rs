@@ -1,310 +1,242 @@
 pub mod debounce {
-    use crate::device::linux::config::ConfigHolder;
+    use crate::device::linux::config::key_class;
+    use crate::device::linux::logging::redaction_enabled;
+    use debounce_keyboard::config::Keymap;
+    use debounce_keyboard::corrections::{Correction, CorrectionKind};
+    use debounce_keyboard::engine::{KeyEvent, PRESS};
+    use debounce_keyboard::io::{InputSource, OutputSink};
     use evdev::uinput::VirtualDevice;
     use evdev::{
         AttributeSet, Device as DeviceEvDev, EventSummary, InputEvent, KeyCode,
         KeyEvent as KeyEventEvDev,
     };
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
+    use std::io::ErrorKind;
+    use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+    use std::str::FromStr;
+    use std::sync::LazyLock;
+    use std::sync::mpsc::{Receiver, channel};
     use std::time::{Duration, SystemTime};
 
-    const ALL_KEYS: [u16; 244] = [
-        KeyCode::KEY_RESERVED.code(),
-        KeyCode::KEY_ESC.code(),
-        KeyCode::KEY_1.code(),
-        KeyCode::KEY_2.code(),
-        KeyCode::KEY_3.code(),
-        KeyCode::KEY_4.code(),
-        KeyCode::KEY_5.code(),
-        KeyCode::KEY_6.code(),
-        KeyCode::KEY_7.code(),
-        KeyCode::KEY_8.code(),
-        KeyCode::KEY_9.code(),
-        KeyCode::KEY_0.code(),
-        KeyCode::KEY_MINUS.code(),
-        KeyCode::KEY_EQUAL.code(),
-        KeyCode::KEY_BACKSPACE.code(),
-        KeyCode::KEY_TAB.code(),
-        KeyCode::KEY_Q.code(),
-        KeyCode::KEY_W.code(),
-        KeyCode::KEY_E.code(),
-        KeyCode::KEY_R.code(),
-        KeyCode::KEY_T.code(),
-        KeyCode::KEY_Y.code(),
-        KeyCode::KEY_U.code(),
-        KeyCode::KEY_I.code(),
-        KeyCode::KEY_O.code(),
-        KeyCode::KEY_P.code(),
-        KeyCode::KEY_LEFTBRACE.code(),
-        KeyCode::KEY_RIGHTBRACE.code(),
-        KeyCode::KEY_ENTER.code(),
-        KeyCode::KEY_LEFTCTRL.code(),
-        KeyCode::KEY_A.code(),
-        KeyCode::KEY_S.code(),
-        KeyCode::KEY_D.code(),
-        KeyCode::KEY_F.code(),
-        KeyCode::KEY_G.code(),
-        KeyCode::KEY_H.code(),
-        KeyCode::KEY_J.code(),
-        KeyCode::KEY_K.code(),
-        KeyCode::KEY_L.code(),
-        KeyCode::KEY_SEMICOLON.code(),
-        KeyCode::KEY_APOSTROPHE.code(),
-        KeyCode::KEY_GRAVE.code(),
-        KeyCode::KEY_LEFTSHIFT.code(),
-        KeyCode::KEY_BACKSLASH.code(),
-        KeyCode::KEY_Z.code(),
-        KeyCode::KEY_X.code(),
-        KeyCode::KEY_C.code(),
-        KeyCode::KEY_V.code(),
-        KeyCode::KEY_B.code(),
-        KeyCode::KEY_N.code(),
-        KeyCode::KEY_M.code(),
-        KeyCode::KEY_COMMA.code(),
-        KeyCode::KEY_DOT.code(),
-        KeyCode::KEY_SLASH.code(),
-        KeyCode::KEY_RIGHTSHIFT.code(),
-        KeyCode::KEY_KPASTERISK.code(),
-        KeyCode::KEY_LEFTALT.code(),
-        KeyCode::KEY_SPACE.code(),
-        KeyCode::KEY_CAPSLOCK.code(),
-        KeyCode::KEY_F1.code(),
-        KeyCode::KEY_F2.code(),
-        KeyCode::KEY_F3.code(),
-        KeyCode::KEY_F4.code(),
-        KeyCode::KEY_F5.code(),
-        KeyCode::KEY_F6.code(),
-        KeyCode::KEY_F7.code(),
-        KeyCode::KEY_F8.code(),
-        KeyCode::KEY_F9.code(),
-        KeyCode::KEY_F10.code(),
-        KeyCode::KEY_NUMLOCK.code(),
-        KeyCode::KEY_SCROLLLOCK.code(),
-        KeyCode::KEY_KP7.code(),
-        KeyCode::KEY_KP8.code(),
-        KeyCode::KEY_KP9.code(),
-        KeyCode::KEY_KPMINUS.code(),
-        KeyCode::KEY_KP4.code(),
-        KeyCode::KEY_KP5.code(),
-        KeyCode::KEY_KP6.code(),
-        KeyCode::KEY_KPPLUS.code(),
-        KeyCode::KEY_KP1.code(),
-        KeyCode::KEY_KP2.code(),
-        KeyCode::KEY_KP3.code(),
-        KeyCode::KEY_KP0.code(),
-        KeyCode::KEY_KPDOT.code(),
-        KeyCode::KEY_ZENKAKUHANKAKU.code(),
-        KeyCode::KEY_102ND.code(),
-        KeyCode::KEY_F11.code(),
-        KeyCode::KEY_F12.code(),
-        KeyCode::KEY_RO.code(),
-        KeyCode::KEY_KATAKANA.code(),
-        KeyCode::KEY_HIRAGANA.code(),
-        KeyCode::KEY_HENKAN.code(),
-        KeyCode::KEY_KATAKANAHIRAGANA.code(),
-        KeyCode::KEY_MUHENKAN.code(),
-        KeyCode::KEY_KPJPCOMMA.code(),
-        KeyCode::KEY_KPENTER.code(),
-        KeyCode::KEY_RIGHTCTRL.code(),
-        KeyCode::KEY_KPSLASH.code(),
-        KeyCode::KEY_SYSRQ.code(),
-        KeyCode::KEY_RIGHTALT.code(),
-        KeyCode::KEY_LINEFEED.code(),
-        KeyCode::KEY_HOME.code(),
-        KeyCode::KEY_UP.code(),
-        KeyCode::KEY_PAGEUP.code(),
-        KeyCode::KEY_LEFT.code(),
-        KeyCode::KEY_RIGHT.code(),
-        KeyCode::KEY_END.code(),
-        KeyCode::KEY_DOWN.code(),
-        KeyCode::KEY_PAGEDOWN.code(),
-        KeyCode::KEY_INSERT.code(),
-        KeyCode::KEY_DELETE.code(),
-        KeyCode::KEY_MACRO.code(),
-        KeyCode::KEY_MUTE.code(),
-        KeyCode::KEY_VOLUMEDOWN.code(),
-        KeyCode::KEY_VOLUMEUP.code(),
-        KeyCode::KEY_POWER.code(),
-        KeyCode::KEY_KPEQUAL.code(),
-        KeyCode::KEY_KPPLUSMINUS.code(),
-        KeyCode::KEY_PAUSE.code(),
-        KeyCode::KEY_SCALE.code(),
-        KeyCode::KEY_KPCOMMA.code(),
-        KeyCode::KEY_HANGEUL.code(),
-        KeyCode::KEY_HANJA.code(),
-        KeyCode::KEY_YEN.code(),
-        KeyCode::KEY_LEFTMETA.code(),
-        KeyCode::KEY_RIGHTMETA.code(),
-        KeyCode::KEY_COMPOSE.code(),
-        KeyCode::KEY_STOP.code(),
-        KeyCode::KEY_AGAIN.code(),
-        KeyCode::KEY_PROPS.code(),
-        KeyCode::KEY_UNDO.code(),
-        KeyCode::KEY_FRONT.code(),
-        KeyCode::KEY_COPY.code(),
-        KeyCode::KEY_OPEN.code(),
-        KeyCode::KEY_PASTE.code(),
-        KeyCode::KEY_FIND.code(),
-        KeyCode::KEY_CUT.code(),
-        KeyCode::KEY_HELP.code(),
-        KeyCode::KEY_MENU.code(),
-        KeyCode::KEY_CALC.code(),
-        KeyCode::KEY_SETUP.code(),
-        KeyCode::KEY_SLEEP.code(),
-        KeyCode::KEY_WAKEUP.code(),
-        KeyCode::KEY_FILE.code(),
-        KeyCode::KEY_SENDFILE.code(),
-        KeyCode::KEY_DELETEFILE.code(),
-        KeyCode::KEY_XFER.code(),
-        KeyCode::KEY_PROG1.code(),
-        KeyCode::KEY_PROG2.code(),
-        KeyCode::KEY_WWW.code(),
-        KeyCode::KEY_MSDOS.code(),
-        KeyCode::KEY_COFFEE.code(),
-        KeyCode::KEY_DIRECTION.code(),
-        KeyCode::KEY_ROTATE_DISPLAY.code(),
-        KeyCode::KEY_CYCLEWINDOWS.code(),
-        KeyCode::KEY_MAIL.code(),
-        KeyCode::KEY_BOOKMARKS.code(),
-        KeyCode::KEY_COMPUTER.code(),
-        KeyCode::KEY_BACK.code(),
-        KeyCode::KEY_FORWARD.code(),
-        KeyCode::KEY_CLOSECD.code(),
-        KeyCode::KEY_EJECTCD.code(),
-        KeyCode::KEY_EJECTCLOSECD.code(),
-        KeyCode::KEY_NEXTSONG.code(),
-        KeyCode::KEY_PLAYPAUSE.code(),
-        KeyCode::KEY_PREVIOUSSONG.code(),
-        KeyCode::KEY_STOPCD.code(),
-        KeyCode::KEY_RECORD.code(),
-        KeyCode::KEY_REWIND.code(),
-        KeyCode::KEY_PHONE.code(),
-        KeyCode::KEY_ISO.code(),
-        KeyCode::KEY_CONFIG.code(),
-        KeyCode::KEY_HOMEPAGE.code(),
-        KeyCode::KEY_REFRESH.code(),
-        KeyCode::KEY_EXIT.code(),
-        KeyCode::KEY_MOVE.code(),
-        KeyCode::KEY_EDIT.code(),
-        KeyCode::KEY_SCROLLUP.code(),
-        KeyCode::KEY_SCROLLDOWN.code(),
-        KeyCode::KEY_KPLEFTPAREN.code(),
-        KeyCode::KEY_KPRIGHTPAREN.code(),
-        KeyCode::KEY_NEW.code(),
-        KeyCode::KEY_REDO.code(),
-        KeyCode::KEY_F13.code(),
-        KeyCode::KEY_F14.code(),
-        KeyCode::KEY_F15.code(),
-        KeyCode::KEY_F16.code(),
-        KeyCode::KEY_F17.code(),
-        KeyCode::KEY_F18.code(),
-        KeyCode::KEY_F19.code(),
-        KeyCode::KEY_F20.code(),
-        KeyCode::KEY_F21.code(),
-        KeyCode::KEY_F22.code(),
-        KeyCode::KEY_F23.code(),
-        KeyCode::KEY_F24.code(),
-        KeyCode::KEY_PLAYCD.code(),
-        KeyCode::KEY_PAUSECD.code(),
-        KeyCode::KEY_PROG3.code(),
-        KeyCode::KEY_PROG4.code(),
-        KeyCode::KEY_DASHBOARD.code(),
-        KeyCode::KEY_SUSPEND.code(),
-        KeyCode::KEY_CLOSE.code(),
-        KeyCode::KEY_PLAY.code(),
-        KeyCode::KEY_FASTFORWARD.code(),
-        KeyCode::KEY_BASSBOOST.code(),
-        KeyCode::KEY_PRINT.code(),
-        KeyCode::KEY_HP.code(),
-        KeyCode::KEY_CAMERA.code(),
-        KeyCode::KEY_SOUND.code(),
-        KeyCode::KEY_QUESTION.code(),
-        KeyCode::KEY_EMAIL.code(),
-        KeyCode::KEY_CHAT.code(),
-        KeyCode::KEY_SEARCH.code(),
-        KeyCode::KEY_CONNECT.code(),
-        KeyCode::KEY_FINANCE.code(),
-        KeyCode::KEY_SPORT.code(),
-        KeyCode::KEY_SHOP.code(),
-        KeyCode::KEY_ALTERASE.code(),
-        KeyCode::KEY_CANCEL.code(),
-        KeyCode::KEY_BRIGHTNESSDOWN.code(),
-        KeyCode::KEY_BRIGHTNESSUP.code(),
-        KeyCode::KEY_MEDIA.code(),
-        KeyCode::KEY_SWITCHVIDEOMODE.code(),
-        KeyCode::KEY_KBDILLUMTOGGLE.code(),
-        KeyCode::KEY_KBDILLUMDOWN.code(),
-        KeyCode::KEY_KBDILLUMUP.code(),
-        KeyCode::KEY_SEND.code(),
-        KeyCode::KEY_REPLY.code(),
-        KeyCode::KEY_FORWARDMAIL.code(),
-        KeyCode::KEY_SAVE.code(),
-        KeyCode::KEY_DOCUMENTS.code(),
-        KeyCode::KEY_BATTERY.code(),
-        KeyCode::KEY_BLUETOOTH.code(),
-        KeyCode::KEY_WLAN.code(),
-        KeyCode::KEY_UWB.code(),
-        KeyCode::KEY_UNKNOWN.code(),
-        KeyCode::KEY_VIDEO_NEXT.code(),
-        KeyCode::KEY_VIDEO_PREV.code(),
-        KeyCode::KEY_BRIGHTNESS_CYCLE.code(),
-        KeyCode::KEY_BRIGHTNESS_AUTO.code(),
-        KeyCode::KEY_DISPLAY_OFF.code(),
-        KeyCode::KEY_WWAN.code(),
-        KeyCode::KEY_RFKILL.code(),
-        KeyCode::KEY_MICMUTE.code(),
-    ];
+    /// Number of key codes the kernel defines (KEY_CNT).
+    const KEY_COUNT: u16 = 0x300;
+    const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+    /// How long reads wait for input before giving their caller a chance to stop.
+    pub const READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+    /// Every key code evdev knows a name for, generated from evdev's own name table.
+    static KEY_NAMES: LazyLock<Vec<(String, u16)>> = LazyLock::new(|| {
+        (0..KEY_COUNT)
+            .map(|code| (format!("{:?}", KeyCode::new(code)), code))
+            .filter(|(name, _)| !name.starts_with("unknown"))
+            .collect()
+    });
 
+    /// Returns every `KEY_*` code; `BTN_*` codes have to be listed explicitly.
     pub fn get_all_keys_code() -> Vec<u16> {
-        ALL_KEYS.into_iter().collect()
+        KEY_NAMES
+            .iter()
+            .filter(|(name, _)| name.starts_with("KEY_"))
+            .map(|&(_, code)| code)
+            .collect()
+    }
+
+    /// Parses an evdev key name (`KEY_A`, `BTN_LEFT`, ...) or a numeric code (`0x1c`, `28`).
+    pub fn key_code_from_name(name: &str) -> Option<u16> {
+        if let Ok(key) = KeyCode::from_str(name) {
+            return Some(key.code());
+        }
+        let code = match name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+            None => name.parse::<u16>().ok()?,
+        };
+        (code < KEY_COUNT).then_some(code)
+    }
+
+    /// Returns the evdev name of a key code, or its hexadecimal value when it has none.
+    pub fn key_name(code: u16) -> String {
+        match KEY_NAMES.iter().find(|&&(_, c)| c == code) {
+            Some((name, _)) => name.clone(),
+            None => format!("{code:#x}"),
+        }
+    }
+
+    /// Names keys after evdev's key codes.
+    pub struct EvdevKeys;
+
+    impl Keymap for EvdevKeys {
+        type Key = u16;
+
+        fn key(name: &str) -> Option<u16> {
+            key_code_from_name(name)
+        }
+
+        fn name(code: u16) -> String {
+            key_name(code)
+        }
+
+        fn all_keys() -> Vec<u16> {
+            get_all_keys_code()
+        }
     }
 
+    /// Number of presses let through and suppressed for a key, and of corrections.
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct KeyStatistics {
+        pub passed: u64,
+        pub suppressed: u64,
+        /// Bounces let through then deleted.
+        pub missed_bounces: u64,
+        /// Deliberate presses suppressed then typed again.
+        pub false_positives: u64,
+    }
+
+    /// Upper bounds, in seconds, of the suppressed interval histogram buckets.
+    const INTERVAL_BUCKETS: [f64; 10] = [
+        0.001, 0.002, 0.005, 0.01, 0.02, 0.035, 0.05, 0.075, 0.1, 0.25,
+    ];
+    /// Upper bounds, in seconds, of the processing latency histogram buckets.
+    const LATENCY_BUCKETS: [f64; 8] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.1];
+
+    /// Distribution of durations over fixed buckets, as Prometheus histograms expect.
     #[derive(Debug)]
-    pub struct KeyEventHolder {
-        minimum_delay: Duration,
-        container: HashMap<u16, KeyEvent>,
+    pub struct Histogram {
+        bounds: &'static [f64],
+        counts: Vec<u64>,
+        sum: f64,
+        count: u64,
     }
 
-    impl KeyEventHolder {
-        pub fn new(minimum_delay: u64) -> KeyEventHolder {
-            KeyEventHolder {
-                minimum_delay: Duration::from_millis(minimum_delay),
-                container: HashMap::new(),
+    impl Histogram {
+        fn new(bounds: &'static [f64]) -> Histogram {
+            Histogram {
+                bounds,
+                counts: vec![0; bounds.len()],
+                sum: 0.0,
+                count: 0,
             }
         }
 
-        fn insert_event(&mut self, key_code: u16, key_event: KeyEvent) {
-            self.container.insert(key_code, key_event);
+        fn observe(&mut self, duration: Duration) {
+            let seconds = duration.as_secs_f64();
+            if let Some(i) = self.bounds.iter().position(|&bound| seconds <= bound) {
+                self.counts[i] += 1;
+            }
+            self.sum += seconds;
+            self.count += 1;
+        }
+
+        /// Returns the cumulative count of every bucket along with its upper bound.
+        #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+        pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+            self.bounds
+                .iter()
+                .zip(&self.counts)
+                .scan(0, |total, (&bound, &count)| {
+                    *total += count;
+                    Some((bound, *total))
+                })
         }
 
-        fn remove_event(&mut self, key_code: u16) {
-            self.container.remove(&key_code);
+        /// Returns the sum of every observed duration, in seconds.
+        pub fn sum(&self) -> f64 {
+            self.sum
         }
 
-        fn last_timestamp(&mut self, key_code: u16) -> Option<&mut KeyEvent> {
-            self.container.get_mut(&key_code)
+        pub fn count(&self) -> u64 {
+            self.count
         }
     }
 
+    /// Event counts and timings since the daemon started or the last reset.
     #[derive(Debug)]
-    pub struct KeyEvent {
-        pub keycode: u16,
-        pub value: i32,
-        pub timestamp: SystemTime,
-        pub valid: bool,
+    pub struct Statistics {
+        keys: BTreeMap<u16, KeyStatistics>,
+        /// Key events read from the device, repeats and releases included.
+        pub events: u64,
+        pub reconnects: u64,
+        /// Time between a suppressed press and the press it repeated.
+        pub suppressed_intervals: Histogram,
+        /// Time between the kernel reading an event and the daemon handling it.
+        pub latency: Histogram,
     }
 
-    impl KeyEvent {
-        pub fn new(keycode: u16, value: i32, timestamp: SystemTime, valid: bool) -> KeyEvent {
-            KeyEvent {
-                keycode,
-                value,
-                timestamp,
-                valid,
+    impl Default for Statistics {
+        fn default() -> Statistics {
+            Statistics {
+                keys: BTreeMap::new(),
+                events: 0,
+                reconnects: 0,
+                suppressed_intervals: Histogram::new(&INTERVAL_BUCKETS),
+                latency: Histogram::new(&LATENCY_BUCKETS),
             }
         }
     }
 
+    impl Statistics {
+        /// Counts an event, `interval` being the time since the last press let through.
+        pub fn record(&mut self, event: &KeyEvent, skipped: bool, interval: Option<Duration>) {
+            self.events += 1;
+            if event.value != PRESS {
+                return;
+            }
+            let key = self.keys.entry(event.code).or_default();
+            if skipped {
+                key.suppressed += 1;
+                if let Some(interval) = interval {
+                    self.suppressed_intervals.observe(interval);
+                }
+            } else {
+                key.passed += 1;
+            }
+        }
+
+        pub fn record_correction(&mut self, correction: &Correction<u16>) {
+            let key = self.keys.entry(correction.key).or_default();
+            match correction.kind {
+                CorrectionKind::MissedBounce => key.missed_bounces += 1,
+                CorrectionKind::FalsePositive => key.false_positives += 1,
+            }
+        }
+
+        /// Records the time since an event read from the device, timestamped by [`now`].
+        pub fn record_latency(&mut self, timestamp: Duration) {
+            self.latency.observe(now().saturating_sub(timestamp));
+        }
+
+        pub fn reset(&mut self) {
+            *self = Statistics::default();
+        }
+
+        /// Returns whether a press of this key has been suppressed since the last reset.
+        pub fn has_suppressions(&self, code: u16) -> bool {
+            self.keys.get(&code).is_some_and(|key| key.suppressed > 0)
+        }
+
+        /// Returns the counts of every key pressed so far, ordered by key code.
+        pub fn keys(&self) -> impl Iterator<Item = (u16, KeyStatistics)> + '_ {
+            self.keys.iter().map(|(&code, &stats)| (code, stats))
+        }
+
+        /// Returns the counts of every key pressed so far by name, except that keys never
+        /// suppressed are grouped by class when redacting.
+        pub fn labeled_keys(&self) -> BTreeMap<String, KeyStatistics> {
+            let mut keys = BTreeMap::<String, KeyStatistics>::new();
+            for (code, stats) in self.keys() {
+                let key = match redaction_enabled() && stats.suppressed == 0 {
+                    true => key_class(code).to_owned(),
+                    false => key_name(code),
+                };
+                let counts = keys.entry(key).or_default();
+                counts.passed += stats.passed;
+                counts.suppressed += stats.suppressed;
+                counts.missed_bounces += stats.missed_bounces;
+                counts.false_positives += stats.false_positives;
+            }
+            keys
+        }
+    }
+
     pub struct Device {
         pub vendor: u16,
         pub product: u16,
@@ -320,8 +252,10 @@ pub mod debounce {
             }
         }
 
-        pub fn grab(&mut self) {
-            self.device_internal.grab().expect("Error on grab device");
+        /// Takes the device for this process alone, so that only filtered events reach
+        /// the rest of the system.
+        pub fn grab(&mut self) -> std::io::Result<()> {
+            self.device_internal.grab()
         }
 
         #[allow(dead_code)]
@@ -332,52 +266,6 @@ pub mod debounce {
         }
     }
 
-    pub fn should_skip(
-        ev: &KeyEvent,
-        key_holder: &mut KeyEventHolder,
-        config_holder: &ConfigHolder,
-    ) -> bool {
-        if ev.value == 2 {
-            return false;
-        }
-        if !config_holder.keys.contains(&ev.keycode) {
-            return false;
-        }
-        let minimum_delay = key_holder.minimum_delay;
-        match key_holder.last_timestamp(ev.keycode) {
-            Some(key_event) => {
-                let duration = ev.timestamp.duration_since(key_event.timestamp).unwrap();
-                let time_expired = duration > minimum_delay;
-                // 1 for pressed, 0 for not pressed
-                if ev.value == 1 {
-                    if time_expired {
-                        key_event.valid = true;
-                        key_holder.insert_event(
-                            ev.keycode,
-                            KeyEvent::new(ev.keycode, ev.value, ev.timestamp, true),
-                        );
-                        return false;
-                    }
-                    true
-                } else if key_event.valid {
-                    key_event.valid = false;
-                    return false;
-                } else {
-                    return true;
-                }
-            }
-            None => {
-                if ev.value == 1 {
-                    key_holder.insert_event(
-                        ev.keycode,
-                        KeyEvent::new(ev.keycode, ev.value, ev.timestamp, true),
-                    );
-                }
-                false
-            }
-        }
-    }
-
     pub fn list_devices() -> Vec<Device> {
         evdev::enumerate()
             .map(|(_, device)| {
@@ -390,41 +278,151 @@ pub mod debounce {
             .collect::<Vec<Device>>()
     }
 
-    pub fn receive_event(device: &mut Device) -> Vec<KeyEvent> {
-        let result: Vec<InputEvent> = device.device_internal.fetch_events().unwrap().collect();
-        result
-            .into_iter()
-            .filter_map(|event| {
-                if let EventSummary::Key(event, _, _) = event.destructure() {
-                    return Some(KeyEvent::new(
-                        event.code().code(),
-                        event.value(),
-                        event.timestamp(),
-                        true,
-                    ));
+    /// Finds a device by its identity, as stored in the config file.
+    pub fn find_device(vendor: u16, product: u16, name: &str) -> Option<Device> {
+        list_devices().into_iter().find(|d| {
+            d.vendor == vendor && d.product == product && d.device_internal.name() == Some(name)
+        })
+    }
+
+    /// Blocks until a device unplugged or lost is available again.
+    pub fn wait_for_device(vendor: u16, product: u16, name: &str) -> Device {
+        loop {
+            if let Some(device) = find_device(vendor, product, name) {
+                return device;
+            }
+            std::thread::sleep(RECONNECT_INTERVAL);
+        }
+    }
+
+    /// Waits up to `timeout` for `fd` to be readable, returning whether it is. A signal
+    /// interrupting the wait counts as a timeout.
+    pub fn wait_readable(fd: BorrowedFd, timeout: Duration) -> std::io::Result<bool> {
+        let mut poll_fd = libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll_fd` is a single valid pollfd for the duration of the call.
+        match unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) } {
+            -1 => match std::io::Error::last_os_error() {
+                e if e.kind() == ErrorKind::Interrupted => Ok(false),
+                e => Err(e),
+            },
+            ready => Ok(ready > 0),
+        }
+    }
+
+    impl InputSource for Device {
+        /// Reads key events, skipping batches made only of other events. Fails with
+        /// [`ErrorKind::Interrupted`] when none came within [`READ_TIMEOUT`].
+        fn read(&mut self) -> std::io::Result<Vec<KeyEvent>> {
+            loop {
+                if !wait_readable(self.device_internal.as_fd(), READ_TIMEOUT)? {
+                    return Err(ErrorKind::Interrupted.into());
                 }
-                None
-            })
-            .collect()
+                let result: Vec<InputEvent> = self.device_internal.fetch_events()?.collect();
+                let events = result
+                    .into_iter()
+                    .filter_map(|event| {
+                        if let EventSummary::Key(event, _, _) = event.destructure() {
+                            let timestamp = event
+                                .timestamp()
+                                .duration_since(SystemTime::UNIX_EPOCH)
+                                .unwrap_or_default();
+                            return Some(KeyEvent::new(
+                                event.code().code(),
+                                event.value(),
+                                timestamp,
+                            ));
+                        }
+                        None
+                    })
+                    .collect::<Vec<_>>();
+                if !events.is_empty() {
+                    return Ok(events);
+                }
+            }
+        }
+
+        /// Waits for the same device to be plugged back in, and grabs it again. Gives up
+        /// when it cannot be grabbed.
+        fn reconnect(&mut self, error: &std::io::Error) -> bool {
+            let name = self
+                .device_internal
+                .name()
+                .unwrap_or("Unknown device")
+                .to_owned();
+            log::warn!("Lost {name}: {error}, waiting for it to come back");
+            *self = wait_for_device(self.vendor, self.product, &name);
+            if let Err(e) = self.grab() {
+                log::error!("Failed to grab {name} again: {e}");
+                return false;
+            }
+            log::info!("Reconnected to {name}");
+            true
+        }
     }
 
-    pub fn emit_key_event(event: KeyEvent, virtual_device: &mut VirtualDevice) {
-        let key_event = *KeyEventEvDev::new(KeyCode(event.keycode), event.value);
-        virtual_device.emit(&[key_event]).unwrap();
+    /// Reads key events on a thread of their own, until the source fails.
+    pub fn read_in_background(mut source: impl InputSource + Send + 'static) -> Receiver<KeyEvent> {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            loop {
+                let events = match source.read() {
+                    Ok(events) => events,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => return,
+                };
+                if events.into_iter().any(|event| sender.send(event).is_err()) {
+                    return;
+                }
+            }
+        });
+        receiver
     }
 
-    pub fn create_virtual_device() -> VirtualDevice {
+    /// Returns the current time on the clock evdev timestamps events with.
+    pub fn now() -> Duration {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    /// The uinput keyboard filtered events are sent to.
+    pub struct VirtualKeyboard(VirtualDevice);
+
+    impl OutputSink for VirtualKeyboard {
+        fn emit(&mut self, event: KeyEvent) -> std::io::Result<()> {
+            let key_event = *KeyEventEvDev::new(KeyCode(event.code), event.value);
+            self.0.emit(&[key_event])
+        }
+    }
+
+    /// Creates a keyboard able to send every `KEY_*` code along with any other code the
+    /// source device has, such as `BTN_*` or vendor codes, which the kernel would otherwise
+    /// drop.
+    pub fn create_virtual_device(source: &Device) -> VirtualKeyboard {
         let mut keys = AttributeSet::<KeyCode>::new();
         for key in get_all_keys_code() {
             keys.insert(KeyCode::new(key));
         }
-        VirtualDevice::builder()
+        for key in source
+            .device_internal
+            .supported_keys()
+            .into_iter()
+            .flatten()
+        {
+            keys.insert(key);
+        }
+        let device = VirtualDevice::builder()
             .unwrap()
             .name("Virtual Keyboard")
             .with_keys(&keys)
             .unwrap()
             .build()
-            .unwrap()
+            .unwrap();
+        VirtualKeyboard(device)
     }
 
     pub fn combine_u16_to_u32(high: u16, low: u16) -> u32 {
@@ -441,7 +439,8 @@ pub mod debounce {
 pub mod command_line {
     use std::path::PathBuf;
 
-    use clap::{Parser, Subcommand};
+    use crate::device::linux::logging::{LogFormat, Redaction};
+    use clap::{ArgAction, Parser, Subcommand};
 
     #[derive(Parser, Debug)]
     #[command(
@@ -450,10 +449,38 @@ pub mod command_line {
         long_about = "A utility designed to eliminate duplicate keystrokes by setting a delay between key presses when typing."
     )]
     pub struct Cli {
-        /// Path to the config file (if not provided, it will create a config file in the current directory or use an existing one)
+        /// Path to the config file (if not provided, $XDG_CONFIG_HOME/debounce-keyboard/config.ini is used, layered over /etc/debounce-keyboard/config.ini)
         #[arg(short, long, value_name = "path")]
         pub config_path: Option<PathBuf>,
 
+        /// Minimum delay between two presses of the same key, overriding the config files
+        #[arg(long, value_name = "ms")]
+        pub delay_ms: Option<u64>,
+
+        /// Comma separated list of keys to debounce, overriding the config files
+        #[arg(long, value_name = "keys")]
+        pub keys: Option<String>,
+
+        /// Profile to use, overriding the 'profile' setting
+        #[arg(long, value_name = "name")]
+        pub profile: Option<String>,
+
+        /// Log more details, repeat to log every key event
+        #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+        pub verbose: u8,
+
+        /// Log only warnings, repeat to log only errors
+        #[arg(short, long, action = ArgAction::Count, global = true)]
+        pub quiet: u8,
+
+        /// Log format (defaults to 'journal' when started by systemd, 'text' otherwise)
+        #[arg(long, value_enum, global = true)]
+        pub log_format: Option<LogFormat>,
+
+        /// Hide which keys are typed in logs and metrics, except keys being debounced
+        #[arg(long, value_enum, global = true, default_value = "auto")]
+        pub redact: Redaction,
+
         #[command(subcommand)]
         pub command: Option<Commands>,
     }
@@ -468,202 +495,2242 @@ pub mod command_line {
             /// device number from list option [0-n]
             device: String,
         },
+        /// Validate the config file and report every problem found
+        CheckConfig,
+        /// Send a command to the running daemon ('help' lists them)
+        Ctl {
+            /// Control socket (defaults to 'control_socket' from the config)
+            #[arg(long, value_name = "path")]
+            socket: Option<PathBuf>,
+            /// Command and its arguments, e.g. 'delay 60'
+            #[arg(required = true, trailing_var_arg = true)]
+            command: Vec<String>,
+        },
+        /// Filter 'struct input_event' records from stdin to stdout, as an interception-tools
+        /// plugin: intercept -g $DEVNODE | debounce-keyboard plugin | uinput -d $DEVNODE
+        Plugin,
+        /// Filter key events played back from a text file, printing the ones that pass
+        Replay {
+            /// File with one '<milliseconds> <key code> <value>' event per line, '-' for stdin
+            file: PathBuf,
+        },
+        /// Show the time between presses of every key, highlighting chatter, reading the
+        /// keyboard without grabbing it; Ctrl+C prints a summary
+        Test {
+            /// Device number from the list command (defaults to the configured device)
+            #[arg(long, value_name = "n")]
+            device: Option<usize>,
+            /// Highlight presses sooner than this after the previous one (defaults to the
+            /// configured delays)
+            #[arg(long, value_name = "ms")]
+            threshold: Option<u64>,
+        },
+        /// Measure how keys bounce and recommend delays, reading the keyboard without
+        /// grabbing it
+        Calibrate {
+            /// Device number from the list command (defaults to the configured device)
+            #[arg(long, value_name = "n")]
+            device: Option<usize>,
+            /// Comma separated list of keys to measure (defaults to the debounced keys, or
+            /// the letters when every key is debounced)
+            keys: Option<String>,
+        },
     }
 }
 
 pub mod config {
-    use crate::device::linux::debounce::get_all_keys_code;
-    use config::{Config, File, FileFormat};
-    use evdev::KeyCode;
-    use std::collections::HashMap;
-    use std::fmt::{Display, Formatter};
-    use std::io::Write;
-    use std::path::PathBuf;
+    use crate::device::linux::debounce::{EvdevKeys, list_devices, split_u32_to_u16};
+    use debounce_keyboard::config;
+    pub use debounce_keyboard::config::{
+        ConfigSources, check_delay_ms, load_profile, save_config_to_path,
+    };
+    use debounce_keyboard::state;
 
-    const AVAILABLE_KEYS: [(&str, u16); 95] = [
-        ("KEY_ESC", KeyCode::KEY_ESC.code()),
-        ("KEY_1", KeyCode::KEY_1.code()),
-        ("KEY_2", KeyCode::KEY_2.code()),
-        ("KEY_3", KeyCode::KEY_3.code()),
-        ("KEY_4", KeyCode::KEY_4.code()),
-        ("KEY_5", KeyCode::KEY_5.code()),
-        ("KEY_6", KeyCode::KEY_6.code()),
-        ("KEY_7", KeyCode::KEY_7.code()),
-        ("KEY_8", KeyCode::KEY_8.code()),
-        ("KEY_9", KeyCode::KEY_9.code()),
-        ("KEY_0", KeyCode::KEY_0.code()),
-        ("KEY_MINUS", KeyCode::KEY_MINUS.code()),
-        ("KEY_EQUAL", KeyCode::KEY_EQUAL.code()),
-        ("KEY_BACKSPACE", KeyCode::KEY_BACKSPACE.code()),
-        ("KEY_TAB", KeyCode::KEY_TAB.code()),
-        ("KEY_Q", KeyCode::KEY_Q.code()),
-        ("KEY_W", KeyCode::KEY_W.code()),
-        ("KEY_E", KeyCode::KEY_E.code()),
-        ("KEY_R", KeyCode::KEY_R.code()),
-        ("KEY_T", KeyCode::KEY_T.code()),
-        ("KEY_Y", KeyCode::KEY_Y.code()),
-        ("KEY_U", KeyCode::KEY_U.code()),
-        ("KEY_I", KeyCode::KEY_I.code()),
-        ("KEY_O", KeyCode::KEY_O.code()),
-        ("KEY_P", KeyCode::KEY_P.code()),
-        ("KEY_LEFTBRACE", KeyCode::KEY_LEFTBRACE.code()),
-        ("KEY_RIGHTBRACE", KeyCode::KEY_RIGHTBRACE.code()),
-        ("KEY_ENTER", KeyCode::KEY_ENTER.code()),
-        ("KEY_LEFTCTRL", KeyCode::KEY_LEFTCTRL.code()),
-        ("KEY_A", KeyCode::KEY_A.code()),
-        ("KEY_S", KeyCode::KEY_S.code()),
-        ("KEY_D", KeyCode::KEY_D.code()),
-        ("KEY_F", KeyCode::KEY_F.code()),
-        ("KEY_G", KeyCode::KEY_G.code()),
-        ("KEY_H", KeyCode::KEY_H.code()),
-        ("KEY_J", KeyCode::KEY_J.code()),
-        ("KEY_K", KeyCode::KEY_K.code()),
-        ("KEY_L", KeyCode::KEY_L.code()),
-        ("KEY_SEMICOLON", KeyCode::KEY_SEMICOLON.code()),
-        ("KEY_APOSTROPHE", KeyCode::KEY_APOSTROPHE.code()),
-        ("KEY_GRAVE", KeyCode::KEY_GRAVE.code()),
-        ("KEY_LEFTSHIFT", KeyCode::KEY_LEFTSHIFT.code()),
-        ("KEY_BACKSLASH", KeyCode::KEY_BACKSLASH.code()),
-        ("KEY_Z", KeyCode::KEY_Z.code()),
-        ("KEY_X", KeyCode::KEY_X.code()),
-        ("KEY_C", KeyCode::KEY_C.code()),
-        ("KEY_V", KeyCode::KEY_V.code()),
-        ("KEY_B", KeyCode::KEY_B.code()),
-        ("KEY_N", KeyCode::KEY_N.code()),
-        ("KEY_M", KeyCode::KEY_M.code()),
-        ("KEY_COMMA", KeyCode::KEY_COMMA.code()),
-        ("KEY_DOT", KeyCode::KEY_DOT.code()),
-        ("KEY_SLASH", KeyCode::KEY_SLASH.code()),
-        ("KEY_RIGHTSHIFT", KeyCode::KEY_RIGHTSHIFT.code()),
-        ("KEY_KPASTERISK", KeyCode::KEY_KPASTERISK.code()),
-        ("KEY_LEFTALT", KeyCode::KEY_LEFTALT.code()),
-        ("KEY_SPACE", KeyCode::KEY_SPACE.code()),
-        ("KEY_CAPSLOCK", KeyCode::KEY_CAPSLOCK.code()),
-        ("KEY_F1", KeyCode::KEY_F1.code()),
-        ("KEY_F2", KeyCode::KEY_F2.code()),
-        ("KEY_F3", KeyCode::KEY_F3.code()),
-        ("KEY_F4", KeyCode::KEY_F4.code()),
-        ("KEY_F5", KeyCode::KEY_F5.code()),
-        ("KEY_F6", KeyCode::KEY_F6.code()),
-        ("KEY_F7", KeyCode::KEY_F7.code()),
-        ("KEY_F8", KeyCode::KEY_F8.code()),
-        ("KEY_F9", KeyCode::KEY_F9.code()),
-        ("KEY_F10", KeyCode::KEY_F10.code()),
-        ("KEY_NUMLOCK", KeyCode::KEY_NUMLOCK.code()),
-        ("KEY_SCROLLLOCK", KeyCode::KEY_SCROLLLOCK.code()),
-        ("KEY_F11", KeyCode::KEY_F11.code()),
-        ("KEY_F12", KeyCode::KEY_F12.code()),
-        ("KEY_SYSRQ", KeyCode::KEY_SYSRQ.code()),
-        ("KEY_RIGHTALT", KeyCode::KEY_RIGHTALT.code()),
-        ("KEY_HOME", KeyCode::KEY_HOME.code()),
-        ("KEY_UP", KeyCode::KEY_UP.code()),
-        ("KEY_PAGEUP", KeyCode::KEY_PAGEUP.code()),
-        ("KEY_LEFT", KeyCode::KEY_LEFT.code()),
-        ("KEY_RIGHT", KeyCode::KEY_RIGHT.code()),
-        ("KEY_END", KeyCode::KEY_END.code()),
-        ("KEY_DOWN", KeyCode::KEY_DOWN.code()),
-        ("KEY_PAGEDOWN", KeyCode::KEY_PAGEDOWN.code()),
-        ("KEY_INSERT", KeyCode::KEY_INSERT.code()),
-        ("KEY_DELETE", KeyCode::KEY_DELETE.code()),
-        ("KEY_PAUSE", KeyCode::KEY_PAUSE.code()),
-        ("KEY_NUMERIC_0", KeyCode::KEY_NUMERIC_0.code()),
-        ("KEY_NUMERIC_1", KeyCode::KEY_NUMERIC_1.code()),
-        ("KEY_NUMERIC_2", KeyCode::KEY_NUMERIC_2.code()),
-        ("KEY_NUMERIC_3", KeyCode::KEY_NUMERIC_3.code()),
-        ("KEY_NUMERIC_4", KeyCode::KEY_NUMERIC_4.code()),
-        ("KEY_NUMERIC_5", KeyCode::KEY_NUMERIC_5.code()),
-        ("KEY_NUMERIC_6", KeyCode::KEY_NUMERIC_6.code()),
-        ("KEY_NUMERIC_7", KeyCode::KEY_NUMERIC_7.code()),
-        ("KEY_NUMERIC_8", KeyCode::KEY_NUMERIC_8.code()),
-        ("KEY_NUMERIC_9", KeyCode::KEY_NUMERIC_9.code()),
-    ];
+    pub type ConfigHolder = config::ConfigHolder<EvdevKeys>;
+    pub type LearnedState = state::LearnedState<EvdevKeys>;
 
-    pub struct ConfigHolder {
-        pub keys: Vec<u16>,
-        pub device_id: u32,
-        pub device_name: String,
-        pub delay_ms: u64,
+    pub fn load_config(sources: &ConfigSources) -> ConfigHolder {
+        config::load_config(sources)
     }
 
-    impl Display for ConfigHolder {
-        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-            let keys = AVAILABLE_KEYS
-                .into_iter()
-                .filter(|(_, v)| self.keys.contains(v))
-                .map(|(k, _)| k)
-                .collect::<Vec<_>>()
-                .join(",");
-            write!(
-                f,
-                "keys={keys}\ndelay_ms={}\ndevice_id={}\ndevice_name={}",
-                self.delay_ms, self.device_id, self.device_name
+    /// Validates every config file, and that the configured device is connected.
+    pub fn check_config(sources: &ConfigSources) -> Vec<String> {
+        let mut problems = config::check_config::<EvdevKeys>(sources);
+        if let Ok(config) = load_profile(sources, None) {
+            problems.extend(check_device(&config));
+        }
+        problems
+    }
+
+    fn check_device(config: &ConfigHolder) -> Option<String> {
+        if config.device_id == 0 {
+            return None;
+        }
+        let (vendor, product) = split_u32_to_u16(config.device_id);
+        let connected = list_devices().iter().any(|d| {
+            d.vendor == vendor
+                && d.product == product
+                && d.device_internal.name() == Some(config.device_name.as_str())
+        });
+        (!connected).then(|| {
+            format!(
+                "device '{}' ({vendor:04x}:{product:04x}) is not connected",
+                config.device_name
             )
+        })
+    }
+
+    pub fn key_class(code: u16) -> &'static str {
+        config::key_class::<EvdevKeys>(code)
+    }
+
+    pub fn get_keys_code(keys: &[&str]) -> Result<Vec<u16>, String> {
+        config::get_keys_code::<EvdevKeys>(keys)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::device::linux::debounce::key_name;
+        use evdev::KeyCode;
+        use std::path::PathBuf;
+
+        /// Writes `contents` to a file of its own and returns its path.
+        fn write_file(name: &str, contents: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!(
+                "debounce-keyboard-evdev-config-{}",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        }
+
+        #[test]
+        fn resolves_evdev_names_and_numeric_codes() {
+            assert_eq!(
+                get_keys_code(&["KEY_KPENTER", "BTN_LEFT", "0x1c", "200", "0x300"]),
+                Err("invalid key codes: \"0x300\"".to_owned())
+            );
+            assert_eq!(
+                get_keys_code(&["KEY_KPENTER", "BTN_LEFT", "0x1c", "200"]),
+                Ok(vec![
+                    KeyCode::KEY_KPENTER.code(),
+                    KeyCode::BTN_LEFT.code(),
+                    KeyCode::KEY_ENTER.code(),
+                    200,
+                ])
+            );
+            assert_eq!(key_name(KeyCode::BTN_LEFT.code()), "BTN_LEFT");
+            assert_eq!(key_name(0x2ff), "0x2ff");
+            assert_eq!(key_class(KeyCode::KEY_KP1.code()), "@numpad");
+        }
+
+        #[test]
+        fn user_file_and_overrides_beat_system_file() {
+            let system = write_file("system.ini", "delay_ms=40\nkeys=KEY_A\ndevice_id=7\n");
+            let user = write_file("user.ini", "delay_ms=50\nkeys=KEY_B\n");
+            let mut sources = ConfigSources::single_file(&user);
+            sources.system = Some(system);
+            sources.set_override("keys", "KEY_C".to_owned());
+
+            let config = load_profile::<EvdevKeys>(&sources, None).unwrap();
+            assert_eq!(config.device_id, 7);
+            assert_eq!(config.delay_ms, 50);
+            assert_eq!(config.keys, [KeyCode::KEY_C.code()]);
         }
     }
+}
+pub mod control {
+    use crate::device::linux::config::{
+        ConfigHolder, ConfigSources, LearnedState, check_delay_ms, get_keys_code, key_class,
+        load_profile,
+    };
+    use crate::device::linux::debounce::{
+        Histogram, READ_TIMEOUT, Statistics, key_name, wait_readable,
+    };
+    use crate::device::linux::logging::{key_label, redaction_enabled};
+    use debounce_keyboard::engine::{DebounceEngine, Decision, KeyEvent, PRESS};
+    use debounce_keyboard::io::{InputEventRecord, InputSource, OutputSink};
+    use std::collections::BTreeMap;
+    use std::fs::DirBuilder;
+    use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+    use std::os::fd::AsFd;
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{Receiver, Sender, channel};
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::time::Duration;
+
+    /// How often delays learned since the state file was last written are saved.
+    const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// Held from taking what was learned until it is written, so that an older state never
+    /// overwrites a newer one.
+    static SAVING: Mutex<()> = Mutex::new(());
 
-    pub fn load_config(file: &PathBuf) -> ConfigHolder {
-        if !file.exists() {
-            std::fs::File::create(file).expect("Failed to create config file");
+    /// Everything the event loop and the control socket share.
+    pub struct DaemonState {
+        pub sources: ConfigSources,
+        pub config: ConfigHolder,
+        pub engine: DebounceEngine,
+        pub statistics: Statistics,
+        /// Receive the key code of every suppressed press.
+        subscribers: Vec<Sender<u16>>,
+        /// Notified whenever the paused state, delay, keys or profile may have changed.
+        watchers: Vec<Sender<()>>,
+        /// File learned delays are saved to, once read by [`DaemonState::load_state`].
+        state_file: Option<PathBuf>,
+        /// What was learned as last read from or written to the state file.
+        saved: LearnedState,
+    }
+
+    pub type SharedState = Arc<Mutex<DaemonState>>;
+
+    impl DaemonState {
+        pub fn new(sources: ConfigSources, config: ConfigHolder) -> DaemonState {
+            DaemonState {
+                engine: DebounceEngine::new(config.engine_config()),
+                sources,
+                config,
+                statistics: Statistics::default(),
+                subscribers: Vec::new(),
+                watchers: Vec::new(),
+                state_file: None,
+                saved: LearnedState::default(),
+            }
         }
-        let file = File::new(file.to_str().unwrap(), FileFormat::Ini);
 
-        let settings = Config::builder().add_source(file).build().unwrap();
+        /// Takes up the delays learned by previous runs from the state file, and saves
+        /// those learned from now on back to it.
+        pub fn load_state(&mut self) {
+            let path = self.config.state_file.clone();
+            match LearnedState::load(&path) {
+                Ok(state) => {
+                    self.engine.restore_learned_delays(state.delays);
+                    self.engine
+                        .restore_delay_corrections(state.delay_corrections);
+                    self.engine
+                        .restore_bouncing_keys(state.bouncing_keys, state.observed_presses);
+                }
+                Err(e) => log::warn!("Ignoring state file {}: {e}", path.display()),
+            }
+            self.saved = self.learned_state();
+            self.state_file = Some(path);
+        }
 
-        let settings = settings
-            .try_deserialize::<HashMap<String, String>>()
-            .unwrap();
+        /// Returns what was learned and the state file to write it to if it changed since
+        /// it was, counting it as saved.
+        fn unsaved_state(&mut self) -> Option<(PathBuf, LearnedState)> {
+            let path = self.state_file.clone()?;
+            let state = self.learned_state();
+            if state == self.saved {
+                return None;
+            }
+            self.saved = state.clone();
+            Some((path, state))
+        }
+
+        fn learned_state(&self) -> LearnedState {
+            let finder = self.engine.bounce_finder();
+            LearnedState {
+                delays: self.engine.learned_delays(),
+                delay_corrections: self.engine.delay_corrections(),
+                bouncing_keys: finder.map(|f| f.found().clone()).unwrap_or_default(),
+                observed_presses: finder.map_or(0, |f| f.presses()),
+            }
+        }
+
+        /// Returns whether keys are still being learned and how many were found bouncing.
+        fn bouncing_progress(&self) -> Option<(bool, usize)> {
+            let finder = self.engine.bounce_finder()?;
+            Some((finder.is_learning(), finder.found().len()))
+        }
+
+        /// Returns the keys setting that debounces only the keys found bouncing, preceded
+        /// by a comment while they are still being learned.
+        fn suggested_keys(&self) -> Result<Vec<String>, String> {
+            let finder = self
+                .engine
+                .bounce_finder()
+                .ok_or("keys are not learned, see learn_keys")?;
+            let mut lines = Vec::new();
+            if finder.is_learning() {
+                lines.push(format!(
+                    "# still learning, {} presses seen so far",
+                    finder.presses()
+                ));
+            }
+            match finder.found().is_empty() {
+                true => lines.push("# no key found bouncing yet".to_owned()),
+                false => {
+                    let keys = finder.found().iter().map(|&code| key_name(code));
+                    lines.push(format!("keys={}", keys.collect::<Vec<_>>().join(",")));
+                }
+            }
+            Ok(lines)
+        }
+
+        /// Filters an event, returning the events to emit in its place: none, itself, or
+        /// toggle chord keys held back before it, followed by it.
+        pub fn filter(&mut self, event: &KeyEvent) -> Vec<KeyEvent> {
+            let last_press = self.engine.last_press(event.code);
+            let paused = self.engine.is_paused();
+            let bouncing = self.bouncing_progress();
+            let decision = self.engine.process(*event);
+            if self.bouncing_progress() != bouncing
+                && let Ok(suggestion) = self.suggested_keys()
+            {
+                log::info!(
+                    suggestion = suggestion.last().map_or("", String::as_str);
+                    "Keys found bouncing changed"
+                );
+            }
+            if self.engine.is_paused() != paused {
+                log::info!("Debouncing {}", if paused { "resumed" } else { "paused" });
+                self.notify_watchers();
+            }
+            let mut events = Vec::new();
+            for (deferred, decision) in self.engine.take_deferred() {
+                if self.record(&deferred, decision, None) {
+                    events.push(deferred);
+                }
+            }
+            let interval = last_press.and_then(|last| event.timestamp.checked_sub(last));
+            if self.record(event, decision, interval) {
+                events.push(*event);
+            }
+            for correction in self.engine.take_corrections() {
+                self.statistics.record_correction(&correction);
+                let debounced = self.statistics.has_suppressions(correction.key);
+                log::debug!(
+                    key = key_label(correction.key, debounced),
+                    kind = format!("{:?}", correction.kind),
+                    threshold_ms = self.engine.delay_of(&correction.key).as_millis() as u64;
+                    "Corrected"
+                );
+            }
+            events
+        }
+
+        /// Counts and logs what was decided for an event, returning whether to emit it.
+        fn record(
+            &mut self,
+            event: &KeyEvent,
+            decision: Decision,
+            interval: Option<Duration>,
+        ) -> bool {
+            let skip = match decision {
+                Decision::Pass => false,
+                Decision::Suppress => true,
+                Decision::Hold => return false,
+            };
+            self.statistics.record(event, skip, interval);
+            if skip {
+                log::debug!(
+                    key = key_label(event.code, true),
+                    value = event.value,
+                    interval_ms = interval.map_or(0, |i| i.as_millis() as u64),
+                    threshold_ms = self.engine.delay_of(&event.code).as_millis() as u64;
+                    "Suppressed"
+                );
+            } else {
+                let debounced = self.statistics.has_suppressions(event.code);
+                let key = key_label(event.code, debounced);
+                log::trace!(key = key, value = event.value; "Passed");
+            }
+            if skip && event.value == PRESS {
+                self.subscribers
+                    .retain(|subscriber| subscriber.send(event.code).is_ok());
+            }
+            !skip
+        }
+
+        /// Returns a channel receiving the key code of every press suppressed from now on.
+        #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+        pub fn subscribe(&mut self) -> Receiver<u16> {
+            let (sender, receiver) = channel();
+            self.subscribers.push(sender);
+            receiver
+        }
+
+        /// Returns a channel notified whenever the paused state, delay, keys or profile may
+        /// have changed, whatever changed them.
+        #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+        pub fn watch(&mut self) -> Receiver<()> {
+            let (sender, receiver) = channel();
+            self.watchers.push(sender);
+            receiver
+        }
+
+        fn notify_watchers(&mut self) {
+            self.watchers.retain(|watcher| watcher.send(()).is_ok());
+        }
+
+        /// Replaces the running profile, or reloads the configured one when `None`,
+        /// without releasing the grabbed device.
+        pub fn switch_profile(&mut self, profile: Option<&str>) -> Result<(), String> {
+            let config = load_profile(&self.sources, profile).map_err(|e| e.join("; "))?;
+            self.engine.set_config(config.engine_config());
+            self.config = config;
+            self.notify_watchers();
+            Ok(())
+        }
+
+        pub fn next_profile(&mut self) -> Result<(), String> {
+            let profile = self.config.next_profile().map(str::to_owned);
+            self.switch_profile(profile.as_deref())
+        }
+
+        /// Switches to the next profile or reloads the configured one when the matching
+        /// flag was raised since the last call.
+        pub fn apply_signals(&mut self, next_profile: &AtomicBool, reload: &AtomicBool) {
+            let switched = if next_profile.swap(false, Ordering::Relaxed) {
+                Some(self.next_profile())
+            } else if reload.swap(false, Ordering::Relaxed) {
+                Some(self.switch_profile(None))
+            } else {
+                None
+            };
+            match switched {
+                Some(Ok(())) => log::info!("Switched to profile {}", self.config.profile),
+                Some(Err(e)) => log::error!("Failed to switch profile: {e}"),
+                None => {}
+            }
+        }
+
+        /// Runs one control command, returning the lines of its answer.
+        fn execute(&mut self, line: &str) -> Result<Vec<String>, String> {
+            let (command, argument) = match line.trim().split_once(char::is_whitespace) {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (line.trim(), ""),
+            };
+            match (command, argument) {
+                ("status", "") => Ok(vec![
+                    format!("profile {}", self.config.profile),
+                    format!("paused {}", self.engine.is_paused()),
+                    format!("delay_ms {}", self.config.delay_ms),
+                    format!("key_delay_ms {}", self.key_delays().join(",")),
+                    format!("keys {}", self.key_names().join(",")),
+                ]),
+                ("pause", "") => {
+                    self.set_paused(true);
+                    Ok(Vec::new())
+                }
+                ("resume", "") => {
+                    self.set_paused(false);
+                    Ok(Vec::new())
+                }
+                ("delay", "") => Ok(vec![self.config.delay_ms.to_string()]),
+                ("delay", delay_ms) => {
+                    let delay_ms = delay_ms
+                        .parse::<u64>()
+                        .map_err(|e| format!("invalid delay {delay_ms:?}: {e}"))?;
+                    self.set_delay_ms(delay_ms).map(|_| Vec::new())
+                }
+                ("keys", "") => Ok(vec![self.key_names().join(",")]),
+                ("keys", keys) => {
+                    let keys = keys.split(',').map(str::trim).collect::<Vec<_>>();
+                    self.set_keys(&keys).map(|_| Vec::new())
+                }
+                ("stats", "") => {
+                    let statistics = &self.statistics;
+                    let average = |histogram: &Histogram, scale: f64| {
+                        histogram.sum() * scale / histogram.count().max(1) as f64
+                    };
+                    let totals = [
+                        format!("events {}", statistics.events),
+                        format!("reconnects {}", statistics.reconnects),
+                        format!(
+                            "average_suppressed_interval_ms {:.1}",
+                            average(&statistics.suppressed_intervals, 1e3)
+                        ),
+                        format!(
+                            "average_latency_us {:.0}",
+                            average(&statistics.latency, 1e6)
+                        ),
+                    ];
+                    let keys = statistics.labeled_keys().into_iter().map(|(key, stats)| {
+                        format!(
+                            "{key} passed {} suppressed {}",
+                            stats.passed, stats.suppressed
+                        )
+                    });
+                    let key_delays = self
+                        .labeled(self.engine.delays())
+                        .into_iter()
+                        .map(|(key, delay)| format!("{key} delay_ms {}", delay.as_millis()));
+                    let corrections = statistics
+                        .labeled_keys()
+                        .into_iter()
+                        .filter(|(_, stats)| stats.missed_bounces + stats.false_positives > 0)
+                        .map(|(key, stats)| {
+                            format!(
+                                "{key} missed_bounces {} false_positives {}",
+                                stats.missed_bounces, stats.false_positives
+                            )
+                        });
+                    let delays = self.labeled_delays().into_iter().map(|(key, delay)| {
+                        format!("{key} learned_delay_ms {}", delay.as_millis())
+                    });
+                    let delay_corrections = self
+                        .labeled(self.engine.delay_corrections())
+                        .into_iter()
+                        .map(|(key, ms)| format!("{key} delay_correction_ms {ms:+}"));
+                    Ok(totals
+                        .into_iter()
+                        .chain(keys)
+                        .chain(key_delays)
+                        .chain(corrections)
+                        .chain(delays)
+                        .chain(delay_corrections)
+                        .collect())
+                }
+                ("suggest", "") => self.suggested_keys(),
+                ("stats", "reset") => {
+                    self.statistics.reset();
+                    Ok(Vec::new())
+                }
+                ("profile", "") => Ok(self
+                    .config
+                    .profiles
+                    .iter()
+                    .map(|profile| match *profile == self.config.profile {
+                        true => format!("* {profile}"),
+                        false => format!("  {profile}"),
+                    })
+                    .collect()),
+                ("profile", "next") => self.next_profile().map(|_| Vec::new()),
+                ("profile", profile) => self.switch_profile(Some(profile)).map(|_| Vec::new()),
+                ("help", "") => Ok(HELP.lines().map(str::to_owned).collect()),
+                _ => Err(format!("unknown command {:?}, try 'help'", line.trim())),
+            }
+        }
+
+        /// Changes the delay until the configuration is reloaded.
+        pub fn set_delay_ms(&mut self, delay_ms: u64) -> Result<(), String> {
+            let delay_ms = check_delay_ms(delay_ms)?;
+            self.config.delay_ms = delay_ms;
+            self.engine.set_delay(Duration::from_millis(delay_ms));
+            self.notify_watchers();
+            Ok(())
+        }
+
+        /// Changes the debounced keys until the configuration is reloaded.
+        pub fn set_keys(&mut self, keys: &[&str]) -> Result<(), String> {
+            self.config.keys = get_keys_code(keys)?;
+            self.engine.set_keys(self.config.keys.iter().copied());
+            self.notify_watchers();
+            Ok(())
+        }
+
+        pub fn set_paused(&mut self, paused: bool) {
+            self.engine.set_paused(paused);
+            self.notify_watchers();
+        }
+
+        /// Returns the keys that have a delay of their own, as `<key>:<ms>`.
+        pub fn key_delays(&self) -> Vec<String> {
+            self.config
+                .key_delay_ms
+                .iter()
+                .map(|(&code, delay_ms)| format!("{}:{delay_ms}", key_name(code)))
+                .collect()
+        }
+
+        /// Returns the delays learned for every key by name, except that keys never
+        /// suppressed are grouped by class, with the longest delay, when redacting.
+        pub fn labeled_delays(&self) -> BTreeMap<String, Duration> {
+            self.labeled(self.engine.learned_delays())
+        }
+
+        /// Names the keys of `values` as [`Statistics::labeled_keys`] does, keeping the
+        /// largest value of keys grouped together.
+        pub fn labeled<V: Copy + Ord>(&self, values: BTreeMap<u16, V>) -> BTreeMap<String, V> {
+            let mut labeled = BTreeMap::<String, V>::new();
+            for (code, value) in values {
+                let key = match redaction_enabled() && !self.statistics.has_suppressions(code) {
+                    true => key_class(code).to_owned(),
+                    false => key_name(code),
+                };
+                labeled
+                    .entry(key)
+                    .and_modify(|largest| *largest = (*largest).max(value))
+                    .or_insert(value);
+            }
+            labeled
+        }
+
+        /// Returns the names of the debounced keys, ordered by key code.
+        pub fn key_names(&self) -> Vec<String> {
+            let mut codes = self.config.keys.clone();
+            codes.sort_unstable();
+            codes.into_iter().map(key_name).collect()
+        }
+    }
+
+    /// Writes what was learned to the state file if it changed since it was, without
+    /// holding the lock on `state` meanwhile.
+    pub fn save_state(state: &SharedState) {
+        let _saving = SAVING.lock().unwrap_or_else(PoisonError::into_inner);
+        let unsaved = state.lock().unwrap().unsaved_state();
+        if let Some((path, learned)) = unsaved
+            && let Err(e) = learned.save(&path)
+        {
+            log::warn!("Failed to save state to {}: {e}", path.display());
+        }
+    }
+
+    /// Saves what was learned every [`SAVE_INTERVAL`] from a background thread.
+    pub fn save_periodically(state: SharedState) {
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(SAVE_INTERVAL);
+                save_state(&state);
+            }
+        });
+    }
+
+    /// Filters the events of `source` into `sink` until the input ends or `stop` is
+    /// raised, switching to the next profile or reloading the configured one when the
+    /// matching flag is raised.
+    pub fn run(
+        source: &mut impl InputSource,
+        sink: &mut impl OutputSink,
+        state: &SharedState,
+        next_profile: &AtomicBool,
+        reload: &AtomicBool,
+        stop: &AtomicBool,
+    ) -> std::io::Result<()> {
+        let mut last_timestamp = Duration::ZERO;
+        while !stop.load(Ordering::Relaxed) {
+            let events = match source.read() {
+                Ok(events) if events.is_empty() => return Ok(()),
+                Ok(events) => events,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // Keys held when the input went away would otherwise stay pressed.
+                    let releases = state.lock().unwrap().engine.release_held(last_timestamp);
+                    for release in releases {
+                        sink.emit(release)?;
+                    }
+                    if !source.reconnect(&e) {
+                        return Err(e);
+                    }
+                    state.lock().unwrap().statistics.reconnects += 1;
+                    continue;
+                }
+            };
+            let mut state = state.lock().unwrap();
+            state.apply_signals(next_profile, reload);
+            for event in events {
+                last_timestamp = event.timestamp;
+                for event in state.filter(&event) {
+                    sink.emit(event)?;
+                }
+                state.statistics.record_latency(event.timestamp);
+            }
+        }
+        Ok(())
+    }
+
+    /// Filters a stream of `struct input_event` records as an interception-tools plugin,
+    /// passing every event but suppressed keys from `input` to `output` until it ends or
+    /// `stop` is raised. `input` is waited for on its file descriptor, so it must not be
+    /// buffered.
+    pub fn run_plugin(
+        mut input: impl Read + AsFd,
+        mut output: impl Write,
+        state: &SharedState,
+        next_profile: &AtomicBool,
+        reload: &AtomicBool,
+        stop: &AtomicBool,
+    ) -> std::io::Result<()> {
+        let mut bytes = [0; InputEventRecord::SIZE];
+        while !stop.load(Ordering::Relaxed) {
+            if !wait_readable(input.as_fd(), READ_TIMEOUT)? {
+                continue;
+            }
+            match input.read_exact(&mut bytes) {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                result => result?,
+            }
+            let record = InputEventRecord::from_bytes(&bytes);
+            let records = match record.key_event() {
+                Some(event) => {
+                    let mut state = state.lock().unwrap();
+                    state.apply_signals(next_profile, reload);
+                    let events = state.filter(&event);
+                    state.statistics.record_latency(event.timestamp);
+                    events
+                        .iter()
+                        .map(InputEventRecord::from_key_event)
+                        .collect()
+                }
+                None => vec![record],
+            };
+            for record in records {
+                output.write_all(&record.to_bytes())?;
+            }
+            // The next plugin waits for whole records, not for a buffer to fill up.
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    const HELP: &str = "\
+status              show the profile, paused state, delays and keys
+pause | resume      turn filtering off or back on
+delay [ms]          show or change the delay
+keys [list]         show or change the debounced keys, e.g. keys @letters,KEY_SPACE
+stats [reset]       show or reset per-key counts of passed and suppressed presses and of
+                    corrections, the current delays, and the delays learned or corrected
+profile [name|next] list profiles or switch to another one
+suggest             show the keys setting that debounces only the keys found bouncing";
 
-        let keys = match settings.get("keys") {
-            Some(keys) => get_keys_code(keys),
-            None => get_all_keys_code(),
+    /// Binds the control socket, only accessible to its owner, or to `control_group`
+    /// when set, and answers commands on a background thread.
+    pub fn listen(path: &Path, group: Option<&str>, state: SharedState) -> std::io::Result<()> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("{} is used by another daemon", path.display()),
+                ));
+            }
+        }
+        let dir = path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+
+        // The socket is bound in a directory only its owner can enter, so that nobody
+        // can connect before its permissions are set, then moved into place.
+        let private_dir = dir.join(format!(".debounce-keyboard-{}", std::process::id()));
+        DirBuilder::new().mode(0o700).create(&private_dir)?;
+        let private_path = private_dir.join("socket");
+        let result = bind(&private_path, path, group);
+        let _ = std::fs::remove_file(&private_path);
+        let _ = std::fs::remove_dir(&private_dir);
+        let listener = result?;
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&state);
+                std::thread::spawn(move || serve(stream, &state));
+            }
+        });
+        Ok(())
+    }
+
+    /// Binds a socket at `private_path`, sets its permissions and moves it to `path`.
+    fn bind(
+        private_path: &Path,
+        path: &Path,
+        group: Option<&str>,
+    ) -> std::io::Result<UnixListener> {
+        let listener = UnixListener::bind(private_path)?;
+        let mode = match group {
+            Some(group) => {
+                let gid = group_id(group).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("unknown group {group:?}"),
+                    )
+                })?;
+                std::os::unix::fs::chown(private_path, None, Some(gid))?;
+                0o660
+            }
+            None => 0o600,
         };
+        std::fs::set_permissions(private_path, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(private_path, path)?;
+        Ok(listener)
+    }
 
-        let delay_ms = match settings.get("delay_ms") {
-            Some(value) => value.parse::<u64>().unwrap(),
-            None => 85,
+    /// Answers every command of a connection. Each answer is made of zero or more
+    /// lines followed by `ok` or `error: <message>`.
+    fn serve(stream: UnixStream, state: &SharedState) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
         };
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let answer = state.lock().unwrap().execute(&line);
+            let mut response = String::new();
+            match answer {
+                Ok(lines) => {
+                    for line in lines {
+                        response.push_str(&line);
+                        response.push('\n');
+                    }
+                    response.push_str("ok\n");
+                }
+                Err(e) => response.push_str(&format!("error: {e}\n")),
+            }
+            if writer.write_all(response.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Resolves a group name, or a numeric group id, from `/etc/group`.
+    fn group_id(group: &str) -> Option<u32> {
+        if let Ok(gid) = group.parse() {
+            return Some(gid);
+        }
+        std::fs::read_to_string("/etc/group")
+            .ok()?
+            .lines()
+            .map(|line| line.split(':').collect::<Vec<_>>())
+            .find(|fields| fields.len() > 2 && fields[0] == group)
+            .and_then(|fields| fields[2].parse().ok())
+    }
+
+    /// Sends a command to a running daemon, printing its answer. Returns whether the
+    /// command succeeded.
+    pub fn send_command(path: &Path, command: &str) -> bool {
+        let mut stream = UnixStream::connect(path).unwrap_or_else(|e| {
+            eprintln!("Failed to connect to {}: {e}", path.display());
+            crate::device::exit(1)
+        });
+        stream
+            .write_all(format!("{}\n", command.trim()).as_bytes())
+            .expect("Failed to send command");
+
+        for line in BufReader::new(stream).lines() {
+            let line = line.expect("Failed to read answer");
+            if line == "ok" {
+                return true;
+            }
+            if let Some(error) = line.strip_prefix("error: ") {
+                eprintln!("{error}");
+                return false;
+            }
+            println!("{line}");
+        }
+        eprintln!("Connection closed by the daemon");
+        false
+    }
+
+    #[cfg(test)]
+    pub mod tests {
+        use super::*;
+        use crate::device::linux::config::load_config;
+        use debounce_keyboard::engine::RELEASE;
+        use std::os::unix::fs::FileTypeExt;
+        use std::path::PathBuf;
+        use std::time::Duration;
+
+        /// Returns an empty directory of its own for a test.
+        pub fn test_dir(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir()
+                .join(format!("debounce-keyboard-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        /// Returns the state of a daemon configured by `config`, written to `dir`.
+        pub fn test_state(dir: &Path, config: &str) -> SharedState {
+            let path = dir.join("config.ini");
+            std::fs::write(&path, config).unwrap();
+            let sources = ConfigSources::single_file(&path);
+            let config = load_config(&sources);
+            Arc::new(Mutex::new(DaemonState::new(sources, config)))
+        }
 
-        let device_id = match settings.get("device_id") {
-            Some(id) => id.parse::<u32>().expect("Invalid device id"),
-            None => 0,
+        const KEY_A: u16 = 30;
+
+        fn at(ms: u64) -> Duration {
+            Duration::from_millis(ms)
+        }
+
+        #[test]
+        fn run_filters_source_into_sink() {
+            let dir = test_dir("run");
+            let state = test_state(&dir, "delay_ms=50\nkeys=KEY_A\n");
+            let (sender, mut receiver) = channel();
+            let events = [
+                (PRESS, 0),
+                (RELEASE, 10),
+                (PRESS, 20),
+                (RELEASE, 25),
+                (PRESS, 100),
+                (RELEASE, 110),
+            ];
+            for (value, ms) in events {
+                sender.send(KeyEvent::new(KEY_A, value, at(ms))).unwrap();
+            }
+            drop(sender);
+
+            let mut emitted = Vec::new();
+            let no_signal = AtomicBool::new(false);
+            run(
+                &mut receiver,
+                &mut emitted,
+                &state,
+                &no_signal,
+                &no_signal,
+                &no_signal,
+            )
+            .unwrap();
+            let emitted = emitted
+                .iter()
+                .map(|event| (event.value, event.timestamp.as_millis()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                emitted,
+                [(PRESS, 0), (RELEASE, 10), (PRESS, 100), (RELEASE, 110)]
+            );
+            let statistics = &state.lock().unwrap().statistics;
+            assert_eq!(statistics.events, 6);
+            let (_, key) = statistics.keys().next().unwrap();
+            assert_eq!((key.passed, key.suppressed), (2, 1));
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        /// Reads one batch of events, then fails.
+        struct Unplugged(Option<Vec<KeyEvent>>);
+
+        impl InputSource for Unplugged {
+            fn read(&mut self) -> std::io::Result<Vec<KeyEvent>> {
+                self.0.take().ok_or_else(|| ErrorKind::NotConnected.into())
+            }
+        }
+
+        #[test]
+        fn run_releases_held_keys_when_the_source_fails() {
+            let dir = test_dir("run-unplugged");
+            let state = test_state(&dir, "delay_ms=50\nkeys=KEY_A\n");
+            let mut source = Unplugged(Some(vec![KeyEvent::new(KEY_A, PRESS, at(0))]));
+            let mut emitted = Vec::new();
+            let no_signal = AtomicBool::new(false);
+            let error = run(
+                &mut source,
+                &mut emitted,
+                &state,
+                &no_signal,
+                &no_signal,
+                &no_signal,
+            );
+            assert_eq!(error.unwrap_err().kind(), ErrorKind::NotConnected);
+            assert_eq!(
+                emitted,
+                [
+                    KeyEvent::new(KEY_A, PRESS, at(0)),
+                    KeyEvent::new(KEY_A, RELEASE, at(0))
+                ]
+            );
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn commands_change_and_report_the_state() {
+            let dir = test_dir("commands");
+            let state = test_state(
+                &dir,
+                "delay_ms=40\nkeys=KEY_A\n[profiles.gaming]\n[profiles.typing]\n",
+            );
+            let mut state = state.lock().unwrap();
+            for ms in [0, 10, 100] {
+                let timestamp = Duration::from_millis(ms);
+                state.filter(&KeyEvent::new(30, PRESS, timestamp));
+                state.filter(&KeyEvent::new(30, RELEASE, timestamp));
+            }
+            assert_eq!(
+                state.execute("stats").unwrap()[4..],
+                ["KEY_A passed 2 suppressed 1", "KEY_A delay_ms 40"]
+            );
+
+            assert_eq!(state.execute("pause"), Ok(Vec::new()));
+            assert_eq!(state.execute("delay 60"), Ok(Vec::new()));
+            assert_eq!(state.execute("keys KEY_B, KEY_A"), Ok(Vec::new()));
+            assert_eq!(
+                state.execute("status").unwrap(),
+                [
+                    "profile default",
+                    "paused true",
+                    "delay_ms 60",
+                    "key_delay_ms ",
+                    "keys KEY_A,KEY_B"
+                ]
+            );
+            assert_eq!(state.execute("profile gaming"), Ok(Vec::new()));
+            assert_eq!(state.execute("profile").unwrap(), ["* gaming", "  typing"]);
+            assert!(state.execute("delay 0").is_err());
+            assert!(state.execute("bogus").is_err());
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn learned_delays_are_kept_in_the_state_file() {
+            let dir = test_dir("state");
+            let state_file = dir.join("state.json");
+            std::fs::write(&state_file, "{\"learned_delay_ms\": {\"KEY_S\": 40}}").unwrap();
+            let config = format!(
+                "keys=KEY_A,KEY_S\nadaptive_delay=true\nstate_file={}\n",
+                state_file.display()
+            );
+            let shared = test_state(&dir, &config);
+            let mut state = shared.lock().unwrap();
+            state.load_state();
+            // KEY_A bounces 5ms after every press.
+            for i in 0..=10 {
+                for (value, ms) in [(PRESS, 0), (RELEASE, 3), (PRESS, 5), (RELEASE, 8)] {
+                    state.filter(&KeyEvent::new(KEY_A, value, at(i * 150 + ms)));
+                }
+            }
+            let stats = state.execute("stats").unwrap();
+            assert_eq!(
+                stats[stats.len() - 2..],
+                ["KEY_A learned_delay_ms 27", "KEY_S learned_delay_ms 40"]
+            );
+
+            drop(state);
+            save_state(&shared);
+            let saved = std::fs::read_to_string(&state_file).unwrap();
+            assert!(saved.contains("\"KEY_A\": 27"), "{saved}");
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn corrections_are_counted_and_kept() {
+            const KEY_BACKSPACE: u16 = 14;
+            let dir = test_dir("corrections");
+            let state_file = dir.join("state.json");
+            let config = format!(
+                "keys=KEY_A\ndelay_ms=50\ncorrection_step_ms=5\nstate_file={}\n",
+                state_file.display()
+            );
+            let shared = test_state(&dir, &config);
+            let mut state = shared.lock().unwrap();
+            state.load_state();
+            let events = [
+                (KEY_A, PRESS, 0),
+                (KEY_A, RELEASE, 50),
+                (KEY_A, PRESS, 55),
+                (KEY_A, RELEASE, 60),
+                (KEY_BACKSPACE, PRESS, 300),
+            ];
+            for (code, value, ms) in events {
+                state.filter(&KeyEvent::new(code, value, at(ms)));
+            }
+            let stats = state.execute("stats").unwrap();
+            assert_eq!(
+                stats[stats.len() - 2..],
+                [
+                    "KEY_A missed_bounces 1 false_positives 0",
+                    "KEY_A delay_correction_ms +5"
+                ]
+            );
+
+            drop(state);
+            save_state(&shared);
+            let saved = std::fs::read_to_string(&state_file).unwrap();
+            assert!(saved.contains("\"KEY_A\": 5"), "{saved}");
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn keys_found_bouncing_are_suggested_and_kept() {
+            const KEY_S: u16 = 31;
+            let dir = test_dir("bouncing");
+            let state_file = dir.join("state.json");
+            let config = format!(
+                "keys=KEY_A,KEY_S\nlearn_keys=true\nstate_file={}\n",
+                state_file.display()
+            );
+            let shared = test_state(&dir, &config);
+            let mut state = shared.lock().unwrap();
+            assert_eq!(
+                state.execute("suggest").unwrap(),
+                [
+                    "# still learning, 0 presses seen so far",
+                    "# no key found bouncing yet"
+                ]
+            );
+            state.load_state();
+            // KEY_S bounces 5ms after every other press.
+            for i in 0..4 {
+                for (code, value, ms) in [
+                    (KEY_A, PRESS, 0),
+                    (KEY_A, RELEASE, 50),
+                    (KEY_S, PRESS, 100),
+                    (KEY_S, RELEASE, 103),
+                    (KEY_S, PRESS, 108),
+                    (KEY_S, RELEASE, 150),
+                ] {
+                    state.filter(&KeyEvent::new(code, value, at(i * 500 + ms)));
+                }
+            }
+            assert_eq!(
+                state.execute("suggest").unwrap(),
+                ["# still learning, 12 presses seen so far", "keys=KEY_S"]
+            );
+
+            drop(state);
+            save_state(&shared);
+            let saved = std::fs::read_to_string(&state_file).unwrap();
+            assert!(
+                saved.contains("\"bouncing_keys\": [\n    \"KEY_S\"\n  ]"),
+                "{saved}"
+            );
+            assert!(saved.contains("\"observed_presses\": 12"), "{saved}");
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn chattering_keys_show_their_longer_delay() {
+            let dir = test_dir("chatter");
+            let state = test_state(
+                &dir,
+                "keys=KEY_A\ndelay_ms=50\nchatter_bounces=2\nchatter_factor=3\n",
+            );
+            let mut state = state.lock().unwrap();
+            for (value, ms) in [(PRESS, 0), (RELEASE, 5), (PRESS, 10), (RELEASE, 15)] {
+                state.filter(&KeyEvent::new(KEY_A, value, at(ms)));
+            }
+            assert!(
+                state
+                    .execute("stats")
+                    .unwrap()
+                    .contains(&"KEY_A delay_ms 50".to_owned())
+            );
+
+            state.filter(&KeyEvent::new(KEY_A, PRESS, at(30)));
+            assert!(
+                state
+                    .execute("stats")
+                    .unwrap()
+                    .contains(&"KEY_A delay_ms 150".to_owned())
+            );
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn listen_binds_a_private_socket() {
+            let dir = test_dir("listen");
+            let state = test_state(&dir, "delay_ms=40\nkeys=KEY_A\n");
+            let path = dir.join("control.sock");
+            listen(&path, None, state).unwrap();
+
+            let metadata = std::fs::metadata(&path).unwrap();
+            assert!(metadata.file_type().is_socket());
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+            let mut stream = UnixStream::connect(&path).unwrap();
+            stream.write_all(b"delay\n").unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            assert_eq!(lines.next().unwrap().unwrap(), "40");
+            assert_eq!(lines.next().unwrap().unwrap(), "ok");
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn listen_leaves_other_files_alone() {
+            let dir = test_dir("listen-file");
+            let state = test_state(&dir, "delay_ms=40\n");
+            let path = dir.join("control.sock");
+            std::fs::write(&path, "not a socket").unwrap();
+
+            let error = listen(&path, None, state).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
+pub mod calibrate {
+    use crate::device::linux::config::{ConfigHolder, check_delay_ms, save_config_to_path};
+    use crate::device::linux::debounce::key_name;
+    use debounce_keyboard::calibration::{Timings, recommend};
+    use debounce_keyboard::engine::KeyEvent;
+    use std::io::{BufRead, Write};
+    use std::path::PathBuf;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    /// Asks for every key to be tapped at a usual pace and as fast doubles, measures its
+    /// bounces from `events`, then recommends delays and offers to save them to `path`.
+    /// Answers are read from `input`, and every key tapped reaches `input` too, as the
+    /// keyboard is not grabbed, so each step ends with Enter.
+    pub fn calibrate(
+        events: &Receiver<KeyEvent>,
+        keys: &[u16],
+        mut input: impl BufRead,
+        mut output: impl Write,
+        mut config: ConfigHolder,
+        path: &PathBuf,
+    ) -> std::io::Result<()> {
+        let mut read_line = |output: &mut dyn Write| {
+            output.flush()?;
+            let mut line = String::new();
+            input.read_line(&mut line).map(|_| line)
         };
+        writeln!(
+            output,
+            "Each key is measured twice; press Enter without tapping to skip a step."
+        )?;
+        let mut timings = Timings::new();
+        let mut silent = true;
+        for &key in keys {
+            let name = key_name(key);
+            let steps = [
+                format!("Tap {name} about ten times at your usual pace, then press Enter."),
+                format!(
+                    "Double-tap {name} as fast as you can, about five times, then press Enter."
+                ),
+            ];
+            for step in steps {
+                write!(output, "{step} ")?;
+                read_line(&mut output)?;
+                for event in events.try_iter() {
+                    silent = false;
+                    if event.code == key {
+                        timings.observe(&event);
+                    }
+                }
+            }
+            match timings.keys().get(&key) {
+                Some(timing) => writeln!(
+                    output,
+                    "{name}: {}, fastest deliberate press {}",
+                    timing
+                        .longest_bounce()
+                        .map_or("no bounce".to_owned(), |bounce| format!(
+                            "longest bounce {} ({} seen)",
+                            ms(bounce),
+                            timing.bounces.len()
+                        )),
+                    timing.fastest_deliberate().map_or("unknown".to_owned(), ms)
+                )?,
+                None => writeln!(output, "{name}: not measured")?,
+            }
+        }
+        if silent {
+            writeln!(
+                output,
+                "No key events were read: the keyboard may be grabbed by a running daemon."
+            )?;
+            return Ok(());
+        }
 
-        let device_name = match settings.get("device_name") {
-            Some(name) => name.to_owned(),
-            None => "unknown".to_owned(),
+        let recommendation = recommend(timings.keys());
+        let Some(delay) = recommendation.delay else {
+            writeln!(output, "No key bounced, so debouncing may not be needed.")?;
+            return Ok(());
         };
+        config.delay_ms = check_delay_ms(millis(delay)).unwrap_or(config.delay_ms);
+        for key in keys {
+            config.key_delay_ms.remove(key);
+        }
+        for (key, delay) in recommendation.key_delays {
+            if let Ok(delay_ms) = check_delay_ms(millis(delay)) {
+                config.key_delay_ms.insert(key, delay_ms);
+            }
+        }
+        writeln!(
+            output,
+            "Recommended settings:\ndelay_ms={}",
+            config.delay_ms
+        )?;
+        if !config.key_delay_ms.is_empty() {
+            let key_delays = config
+                .key_delay_ms
+                .iter()
+                .map(|(&key, delay_ms)| format!("{}:{delay_ms}", key_name(key)))
+                .collect::<Vec<_>>();
+            writeln!(output, "key_delay_ms={}", key_delays.join(","))?;
+        }
+        write!(output, "Save them to {}? [y/N] ", path.display())?;
+        if read_line(&mut output)?.trim().eq_ignore_ascii_case("y") {
+            save_config_to_path(path, &config);
+            writeln!(output, "Saved.")?;
+        }
+        Ok(())
+    }
 
-        ConfigHolder {
-            keys,
-            device_id,
-            device_name,
-            delay_ms,
+    /// Rounds a delay up to whole milliseconds.
+    fn millis(duration: Duration) -> u64 {
+        duration.as_nanos().div_ceil(1_000_000) as u64
+    }
+
+    fn ms(duration: Duration) -> String {
+        format!("{}ms", millis(duration))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::device::linux::config::{ConfigSources, load_config};
+        use crate::device::linux::control::tests::test_dir;
+        use debounce_keyboard::engine::{PRESS, RELEASE};
+        use std::sync::mpsc::channel;
+
+        const KEY_A: u16 = 30;
+        const KEY_S: u16 = 31;
+
+        #[test]
+        fn recommends_and_saves_delays() {
+            let dir = test_dir("calibrate");
+            let path = dir.join("config.ini");
+            std::fs::write(&path, "delay_ms=85\nkey_delay_ms=KEY_A:5,KEY_S:60\n").unwrap();
+            let config = load_config(&ConfigSources::single_file(&path));
+
+            let (sender, events) = channel();
+            let taps = [
+                (PRESS, 0),
+                (RELEASE, 3),
+                (PRESS, 7),
+                (RELEASE, 60),
+                (PRESS, 200),
+                (RELEASE, 260),
+                (PRESS, 350),
+                (RELEASE, 400),
+            ];
+            for (value, ms) in taps {
+                let at = Duration::from_millis(ms);
+                sender.send(KeyEvent::new(KEY_A, value, at)).unwrap();
+            }
+            let mut output = Vec::new();
+            calibrate(
+                &events,
+                &[KEY_A],
+                &b"\n\ny\n"[..],
+                &mut output,
+                config,
+                &path,
+            )
+            .unwrap();
+
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output
+                    .contains("KEY_A: longest bounce 7ms (1 seen), fastest deliberate press 150ms"),
+                "{output}"
+            );
+            assert!(
+                output.contains("delay_ms=33\nkey_delay_ms=KEY_S:60\n"),
+                "{output}"
+            );
+            let config = load_config(&ConfigSources::single_file(&path));
+            assert_eq!(config.delay_ms, 33);
+            assert_eq!(config.key_delay_ms, [(KEY_S, 60)].into());
+            std::fs::remove_dir_all(dir).unwrap();
         }
     }
+}
+pub mod tester {
+    use crate::device::linux::debounce::key_name;
+    use debounce_keyboard::calibration::Timings;
+    use debounce_keyboard::engine::{EngineConfig, KeyEvent, PRESS};
+    use std::collections::BTreeMap;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{Receiver, RecvTimeoutError};
+    use std::time::Duration;
 
-    pub fn save_config_to_path(path: &PathBuf, config: &ConfigHolder) {
-        let mut config_file = std::fs::File::create(path).expect("Failed to create config file");
-        config_file
-            .write_all(config.to_string().as_bytes())
-            .expect("Failed to write to config file");
+    /// How often the stop flag is checked while no key is pressed.
+    const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// What was seen of a key while testing.
+    #[derive(Debug, Default)]
+    struct KeyReport {
+        presses: u64,
+        /// Presses that came sooner than the key's delay after the previous one.
+        fast: u64,
+        bounces: u64,
+        shortest: Option<Duration>,
     }
 
-    fn get_keys_code(keys: &str) -> Vec<u16> {
-        let available_keys_map = AVAILABLE_KEYS.into_iter().collect::<HashMap<&str, u16>>();
-        let keys_code = keys
-            .split(',')
-            .map(|k| (k, available_keys_map.get(k)))
+    /// Prints every press read from `events` with the time since the previous press of the
+    /// same key, highlighting presses sooner than the key's delay in `config`, until the
+    /// events end or `stop` is raised. A summary of every key follows.
+    pub fn test_keys(
+        events: &Receiver<KeyEvent>,
+        config: &EngineConfig,
+        mut output: impl Write,
+        highlight: bool,
+        stop: &AtomicBool,
+    ) -> std::io::Result<()> {
+        let mut timings = Timings::new();
+        let mut reports = BTreeMap::<u16, KeyReport>::new();
+        while !stop.load(Ordering::Relaxed) {
+            let event = match events.recv_timeout(STOP_POLL_INTERVAL) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let press = timings.observe(&event);
+            if event.value != PRESS {
+                continue;
+            }
+            let report = reports.entry(event.code).or_default();
+            report.presses += 1;
+            let name = key_name(event.code);
+            let Some(press) = press else {
+                writeln!(output, "{name:<16} {:>8}", "-")?;
+                continue;
+            };
+            report.bounces += u64::from(press.bounce);
+            report.shortest = Some(
+                report
+                    .shortest
+                    .map_or(press.interval, |s| s.min(press.interval)),
+            );
+            let line = format!(
+                "{name:<16} {:>8}  shortest {:>8}",
+                ms(press.interval),
+                report.shortest.map_or_else(String::new, ms)
+            );
+            if press.interval < config.delay_of(&event.code) {
+                report.fast += 1;
+                match highlight {
+                    true => writeln!(output, "\x1b[1;31m{line}  chatter\x1b[0m")?,
+                    false => writeln!(output, "{line}  chatter")?,
+                }
+            } else {
+                writeln!(output, "{line}")?;
+            }
+        }
+
+        writeln!(
+            output,
+            "\n{:<16} {:>8} {:>8} {:>8} {:>10}",
+            "key", "presses", "chatter", "bounces", "shortest"
+        )?;
+        for (&code, report) in &reports {
+            writeln!(
+                output,
+                "{:<16} {:>8} {:>8} {:>8} {:>10}",
+                key_name(code),
+                report.presses,
+                report.fast,
+                report.bounces,
+                report.shortest.map_or_else(|| "-".to_owned(), ms)
+            )?;
+        }
+        let chattering = reports
+            .iter()
+            .filter(|(_, report)| report.fast > 0)
+            .map(|(&code, _)| key_name(code))
             .collect::<Vec<_>>();
+        match chattering.is_empty() {
+            true => writeln!(output, "No key chattered.")?,
+            false => writeln!(output, "Keys that chattered: {}", chattering.join(", "))?,
+        }
+        Ok(())
+    }
+
+    fn ms(duration: Duration) -> String {
+        format!("{:.1}ms", duration.as_secs_f64() * 1e3)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use debounce_keyboard::engine::RELEASE;
+        use std::sync::mpsc::channel;
+
+        const KEY_A: u16 = 30;
+        const KEY_S: u16 = 31;
+
+        #[test]
+        fn highlights_chatter_and_sums_it_up() {
+            let (sender, events) = channel();
+            let taps = [
+                (KEY_A, PRESS, 0),
+                (KEY_A, RELEASE, 3),
+                (KEY_A, PRESS, 7),
+                (KEY_A, RELEASE, 60),
+                (KEY_S, PRESS, 100),
+                (KEY_S, RELEASE, 150),
+                (KEY_A, PRESS, 300),
+                (KEY_A, RELEASE, 340),
+            ];
+            for (code, value, ms) in taps {
+                let at = Duration::from_millis(ms);
+                sender.send(KeyEvent::new(code, value, at)).unwrap();
+            }
+            drop(sender);
+
+            let config = EngineConfig {
+                delay: Duration::from_millis(50),
+                ..EngineConfig::default()
+            };
+            let mut output = Vec::new();
+            let stop = AtomicBool::new(false);
+            test_keys(&events, &config, &mut output, false, &stop).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(
+                output.lines().collect::<Vec<_>>(),
+                [
+                    "KEY_A                   -",
+                    "KEY_A               7.0ms  shortest    7.0ms  chatter",
+                    "KEY_S                   -",
+                    "KEY_A             293.0ms  shortest    7.0ms",
+                    "",
+                    "key               presses  chatter  bounces   shortest",
+                    "KEY_A                   3        1        1      7.0ms",
+                    "KEY_S                   1        0        0          -",
+                    "Keys that chattered: KEY_A",
+                ]
+            );
+        }
+    }
+}
+#[cfg(feature = "dbus")]
+pub mod dbus {
+    use crate::device::linux::control::SharedState;
+    use crate::device::linux::debounce::key_name;
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::mpsc::{Receiver, RecvTimeoutError};
+    use std::time::{Duration, Instant};
+    use zbus::blocking::connection::Builder;
+    use zbus::blocking::{Connection, object_server::InterfaceRef};
+    use zbus::fdo;
+    use zbus::interface;
+    use zbus::object_server::SignalEmitter;
+
+    pub const BUS_NAME: &str = "org.debounce_keyboard.Daemon";
+    pub const OBJECT_PATH: &str = "/org/debounce_keyboard/Daemon";
+    /// Suppressions are batched so that a chattering key cannot flood the bus.
+    const SIGNAL_INTERVAL: Duration = Duration::from_millis(500);
+
+    struct Daemon {
+        state: SharedState,
+    }
+
+    #[interface(name = "org.debounce_keyboard.Daemon")]
+    impl Daemon {
+        #[zbus(property)]
+        fn delay_ms(&self) -> u64 {
+            self.state.lock().unwrap().config.delay_ms
+        }
+
+        #[zbus(property)]
+        fn set_delay_ms(&mut self, delay_ms: u64) -> fdo::Result<()> {
+            self.state
+                .lock()
+                .unwrap()
+                .set_delay_ms(delay_ms)
+                .map_err(fdo::Error::InvalidArgs)
+        }
+
+        #[zbus(property)]
+        fn keys(&self) -> Vec<String> {
+            self.state.lock().unwrap().key_names()
+        }
+
+        #[zbus(property)]
+        fn set_keys(&mut self, keys: Vec<String>) -> fdo::Result<()> {
+            let keys = keys.iter().map(String::as_str).collect::<Vec<_>>();
+            self.state
+                .lock()
+                .unwrap()
+                .set_keys(&keys)
+                .map_err(fdo::Error::InvalidArgs)
+        }
+
+        #[zbus(property)]
+        fn paused(&self) -> bool {
+            self.state.lock().unwrap().engine.is_paused()
+        }
+
+        #[zbus(property)]
+        fn set_paused(&mut self, paused: bool) {
+            self.state.lock().unwrap().set_paused(paused);
+        }
+
+        #[zbus(property)]
+        fn profile(&self) -> String {
+            self.state.lock().unwrap().config.profile.clone()
+        }
 
-        if keys_code.iter().all(|(_, v)| v.is_some()) {
-            keys_code.into_iter().map(|(_, v)| *v.unwrap()).collect()
-        } else {
-            let invalid_keys = keys_code
+        #[zbus(property)]
+        fn set_profile(&mut self, profile: String) -> fdo::Result<()> {
+            self.state
+                .lock()
+                .unwrap()
+                .switch_profile(Some(&profile))
+                .map_err(fdo::Error::InvalidArgs)
+        }
+
+        #[zbus(property)]
+        fn profiles(&self) -> Vec<String> {
+            self.state.lock().unwrap().config.profiles.clone()
+        }
+
+        /// Presses suppressed per key name since the daemon started or the last reset.
+        #[zbus(property(emits_changed_signal = "false"))]
+        fn suppression_counts(&self) -> HashMap<String, u64> {
+            self.state
+                .lock()
+                .unwrap()
+                .statistics
+                .labeled_keys()
                 .into_iter()
-                .filter(|&(_, v)| v.is_none())
-                .map(|(k, _)| k)
-                .collect::<Vec<_>>();
-            panic!("invalid key codes: {:?}", invalid_keys.join(","));
+                .map(|(key, stats)| (key, stats.suppressed))
+                .collect()
+        }
+
+        fn reset_statistics(&self) {
+            self.state.lock().unwrap().statistics.reset();
+        }
+
+        /// Emitted for each key at most every half second, with the number of presses
+        /// suppressed since the previous signal.
+        #[zbus(signal)]
+        async fn suppressed(emitter: &SignalEmitter<'_>, key: &str, count: u32)
+        -> zbus::Result<()>;
+    }
+
+    /// Registers the daemon on `bus`, `session`, `system` or a bus address, and emits
+    /// the `Suppressed` signal and property changes from background threads.
+    pub fn serve(bus: &str, state: SharedState) -> zbus::Result<Connection> {
+        let builder = match bus {
+            "session" => Builder::session()?,
+            "system" => Builder::system()?,
+            address => Builder::address(address)?,
+        };
+        let (suppressed, changes) = {
+            let mut state = state.lock().unwrap();
+            (state.subscribe(), state.watch())
+        };
+        let connection = builder
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, Daemon { state })?
+            .build()?;
+
+        let interface = || {
+            connection
+                .object_server()
+                .interface::<_, Daemon>(OBJECT_PATH)
+        };
+        let (suppressed_interface, changes_interface) = (interface()?, interface()?);
+        std::thread::spawn(move || emit_suppressed(suppressed, suppressed_interface));
+        std::thread::spawn(move || emit_changes(changes, changes_interface));
+        Ok(connection)
+    }
+
+    /// Emits `PropertiesChanged` for the properties that differ from the last values
+    /// seen, whether they were set over D-Bus, the control socket, a signal or the toggle
+    /// chord.
+    fn emit_changes(changes: Receiver<()>, interface: InterfaceRef<Daemon>) {
+        let snapshot = |daemon: &Daemon| {
+            let state = daemon.state.lock().unwrap();
+            (
+                state.engine.is_paused(),
+                state.config.delay_ms,
+                state.key_names(),
+                state.config.profile.clone(),
+            )
+        };
+        let mut last = snapshot(&interface.get());
+        while changes.recv().is_ok() {
+            while changes.try_recv().is_ok() {}
+            let daemon = interface.get();
+            let current = snapshot(&daemon);
+            let emitter = interface.signal_emitter();
+            let result = zbus::block_on(async {
+                if current.0 != last.0 {
+                    daemon.paused_changed(emitter).await?;
+                }
+                if current.1 != last.1 {
+                    daemon.delay_ms_changed(emitter).await?;
+                }
+                if current.2 != last.2 {
+                    daemon.keys_changed(emitter).await?;
+                }
+                if current.3 != last.3 {
+                    daemon.profile_changed(emitter).await?;
+                }
+                zbus::Result::Ok(())
+            });
+            if let Err(e) = result {
+                log::warn!("Failed to emit D-Bus signal: {e}");
+            }
+            last = current;
+        }
+    }
+
+    fn emit_suppressed(suppressed: Receiver<u16>, interface: InterfaceRef<Daemon>) {
+        let mut pending = BTreeMap::<u16, u32>::new();
+        let mut last_emitted = Instant::now() - SIGNAL_INTERVAL;
+        loop {
+            let received = if pending.is_empty() {
+                suppressed
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                suppressed.recv_timeout(SIGNAL_INTERVAL.saturating_sub(last_emitted.elapsed()))
+            };
+            match received {
+                Ok(code) => *pending.entry(code).or_default() += 1,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            if pending.is_empty() || last_emitted.elapsed() < SIGNAL_INTERVAL {
+                continue;
+            }
+            for (code, count) in std::mem::take(&mut pending) {
+                let key = key_name(code);
+                let signal = Daemon::suppressed(interface.signal_emitter(), &key, count);
+                if let Err(e) = zbus::block_on(signal) {
+                    log::warn!("Failed to emit D-Bus signal: {e}");
+                }
+            }
+            last_emitted = Instant::now();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::device::linux::control::tests::{test_dir, test_state};
+        use debounce_keyboard::engine::{KeyEvent, PRESS};
+        use std::io::{BufRead, BufReader};
+        use std::process::{Child, Command, Stdio};
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::mpsc::{Sender, channel};
+        use zbus::blocking::fdo::PropertiesProxy;
+
+        /// A private bus run by `dbus-daemon`, stopped when dropped.
+        struct Bus {
+            daemon: Child,
+            address: String,
+        }
+
+        impl Bus {
+            fn start() -> Option<Bus> {
+                let mut daemon = Command::new("dbus-daemon")
+                    .args(["--session", "--print-address", "--nofork", "--nopidfile"])
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .ok()?;
+                let mut address = String::new();
+                BufReader::new(daemon.stdout.as_mut()?)
+                    .read_line(&mut address)
+                    .ok()?;
+                let address = address.trim().to_owned();
+                Some(Bus { daemon, address })
+            }
+        }
+
+        impl Drop for Bus {
+            fn drop(&mut self) {
+                let _ = self.daemon.kill();
+                let _ = self.daemon.wait();
+            }
+        }
+
+        /// Forwards the name of every property announced as changed.
+        fn watch_properties(address: &str, changed: Sender<String>) -> zbus::Result<()> {
+            let connection = Builder::address(address)?.build()?;
+            let properties = PropertiesProxy::builder(&connection)
+                .destination(BUS_NAME)?
+                .path(OBJECT_PATH)?
+                .build()?;
+            let signals = properties.receive_properties_changed()?;
+            std::thread::spawn(move || {
+                let _connection = connection;
+                for signal in signals {
+                    let Ok(args) = signal.args() else { continue };
+                    for name in args.changed_properties().keys() {
+                        if changed.send(name.to_string()).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+            Ok(())
+        }
+
+        fn expect_changed(changed: &Receiver<String>, property: &str) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+                if changed
+                    .recv_timeout(timeout)
+                    .is_ok_and(|name| name == property)
+                {
+                    return;
+                }
+            }
+            panic!("{property} was not announced as changed");
+        }
+
+        #[test]
+        fn properties_changed_whatever_changes_them() {
+            let Some(bus) = Bus::start() else {
+                eprintln!("skipped: dbus-daemon is not available");
+                return;
+            };
+            let dir = test_dir("dbus");
+            let state = test_state(
+                &dir,
+                "delay_ms=50\nkeys=KEY_A\ntoggle_hotkey=KEY_LEFTCTRL+KEY_PAUSE\n\
+                 [profiles.gaming]\ndelay_ms=20\n",
+            );
+            let _connection = serve(&bus.address, Arc::clone(&state)).unwrap();
+            let (sender, changed) = channel();
+            watch_properties(&bus.address, sender).unwrap();
+
+            // Toggle chord
+            for (code, ms) in [(29, 0), (119, 10)] {
+                let event = KeyEvent::new(code, PRESS, Duration::from_millis(ms));
+                state.lock().unwrap().filter(&event);
+            }
+            expect_changed(&changed, "Paused");
+            // Control socket commands
+            state.lock().unwrap().set_delay_ms(60).unwrap();
+            expect_changed(&changed, "DelayMs");
+            state.lock().unwrap().set_keys(&["KEY_B"]).unwrap();
+            expect_changed(&changed, "Keys");
+            // SIGUSR1
+            let next_profile = AtomicBool::new(true);
+            state
+                .lock()
+                .unwrap()
+                .apply_signals(&next_profile, &AtomicBool::new(false));
+            expect_changed(&changed, "Profile");
+
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
+#[cfg(feature = "metrics")]
+pub mod metrics {
+    use crate::device::linux::control::{DaemonState, SharedState};
+    use crate::device::linux::debounce::Histogram;
+    use std::fmt::Write as _;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const PREFIX: &str = "debounce_keyboard";
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+    const MAX_REQUEST_LINE: u64 = 8192;
+
+    /// Serves the Prometheus text format on `http://<address>/metrics` from a
+    /// background thread.
+    pub fn serve(address: SocketAddr, state: SharedState) -> std::io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&state);
+                std::thread::spawn(move || respond(stream, &state));
+            }
+        });
+        Ok(())
+    }
+
+    fn respond(mut stream: TcpStream, state: &SharedState) {
+        // Clients that connect and say nothing would otherwise hold a thread forever.
+        if stream.set_read_timeout(Some(REQUEST_TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(REQUEST_TIMEOUT)).is_err()
+        {
+            return;
+        }
+        let mut request_line = String::new();
+        if BufReader::new(&stream)
+            .take(MAX_REQUEST_LINE)
+            .read_line(&mut request_line)
+            .is_err()
+        {
+            return;
+        }
+        let (status, body) = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+            ["GET", "/metrics", _] => ("200 OK", render(&state.lock().unwrap())),
+            ["GET", _, _] => ("404 Not Found", "Not found, try /metrics\n".to_owned()),
+            _ => ("405 Method Not Allowed", String::new()),
+        };
+        let _ = write!(
+            stream,
+            "HTTP/1.0 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+    }
+
+    fn render(state: &DaemonState) -> String {
+        let statistics = &state.statistics;
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "events_total",
+            "counter",
+            "Key events read from the device.",
+        );
+        writeln!(out, "{PREFIX}_events_total {}", statistics.events).unwrap();
+
+        header(
+            &mut out,
+            "key_presses_total",
+            "counter",
+            "Key presses let through or suppressed, by key.",
+        );
+        // Classes rather than pseudonyms, Prometheus keeps labels long after the session
+        // pseudonyms belong to.
+        for (key, stats) in statistics.labeled_keys() {
+            let outcomes = [("passed", stats.passed), ("suppressed", stats.suppressed)];
+            for (outcome, count) in outcomes {
+                writeln!(
+                    out,
+                    "{PREFIX}_key_presses_total{{key=\"{key}\",outcome=\"{outcome}\"}} {count}"
+                )
+                .unwrap();
+            }
+        }
+
+        header(
+            &mut out,
+            "key_corrections_total",
+            "counter",
+            "Bounces let through then deleted, and presses suppressed then typed again, by key.",
+        );
+        for (key, stats) in statistics.labeled_keys() {
+            let kinds = [
+                ("missed_bounce", stats.missed_bounces),
+                ("false_positive", stats.false_positives),
+            ];
+            for (kind, count) in kinds.into_iter().filter(|(_, count)| *count > 0) {
+                writeln!(
+                    out,
+                    "{PREFIX}_key_corrections_total{{key=\"{key}\",kind=\"{kind}\"}} {count}"
+                )
+                .unwrap();
+            }
+        }
+
+        histogram(
+            &mut out,
+            "suppressed_interval_seconds",
+            "Time between a suppressed press and the press it repeated.",
+            &statistics.suppressed_intervals,
+        );
+        histogram(
+            &mut out,
+            "processing_latency_seconds",
+            "Time between the kernel reading an event and the daemon handling it.",
+            &statistics.latency,
+        );
+
+        header(
+            &mut out,
+            "device_reconnects_total",
+            "counter",
+            "Times the device was lost and found again.",
+        );
+        writeln!(
+            out,
+            "{PREFIX}_device_reconnects_total {}",
+            statistics.reconnects
+        )
+        .unwrap();
+
+        header(&mut out, "paused", "gauge", "Whether filtering is paused.");
+        let paused = state.engine.is_paused() as u8;
+        writeln!(out, "{PREFIX}_paused {paused}").unwrap();
+
+        header(
+            &mut out,
+            "delay_seconds",
+            "gauge",
+            "Minimum delay between two presses.",
+        );
+        let delay = state.config.delay_ms as f64 / 1000.0;
+        writeln!(out, "{PREFIX}_delay_seconds {delay}").unwrap();
+
+        header(
+            &mut out,
+            "learned_delay_seconds",
+            "gauge",
+            "Minimum delay between two presses learned, by key.",
+        );
+        for (key, delay) in state.labeled_delays() {
+            let delay = delay.as_secs_f64();
+            writeln!(
+                out,
+                "{PREFIX}_learned_delay_seconds{{key=\"{key}\"}} {delay}"
+            )
+            .unwrap();
+        }
+
+        header(
+            &mut out,
+            "key_delay_seconds",
+            "gauge",
+            "Current minimum delay between two presses, lengthened while chattering, by key.",
+        );
+        for (key, delay) in state.labeled(state.engine.delays()) {
+            let delay = delay.as_secs_f64();
+            writeln!(out, "{PREFIX}_key_delay_seconds{{key=\"{key}\"}} {delay}").unwrap();
+        }
+        out
+    }
+
+    fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+        writeln!(out, "# HELP {PREFIX}_{name} {help}").unwrap();
+        writeln!(out, "# TYPE {PREFIX}_{name} {kind}").unwrap();
+    }
+
+    fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+        header(out, name, "histogram", help);
+        for (bound, count) in histogram.buckets() {
+            writeln!(out, "{PREFIX}_{name}_bucket{{le=\"{bound}\"}} {count}").unwrap();
+        }
+        let count = histogram.count();
+        writeln!(out, "{PREFIX}_{name}_bucket{{le=\"+Inf\"}} {count}").unwrap();
+        writeln!(out, "{PREFIX}_{name}_sum {}", histogram.sum()).unwrap();
+        writeln!(out, "{PREFIX}_{name}_count {count}").unwrap();
+    }
+}
+pub mod logging {
+    use crate::device::linux::config::key_class;
+    use crate::device::linux::debounce::key_name;
+    use clap::ValueEnum;
+    use log::kv::{Key, Value, VisitSource};
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use std::hash::{BuildHasher, RandomState};
+    use std::io::{IsTerminal, Write};
+    use std::sync::OnceLock;
+    use std::time::SystemTime;
+
+    /// How keys are named in logs and metrics.
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+    pub enum Redaction {
+        /// 'pseudonym' when logging to the journal or anything but a terminal, 'off' otherwise
+        Auto,
+        /// Replace keys with names that only last until the daemon restarts
+        Pseudonym,
+        /// Replace keys with their class, such as '@letters'
+        Class,
+        /// Log key names
+        Off,
+    }
+
+    /// The resolved redaction mode, along with the per-session pseudonym seed.
+    static REDACTION: OnceLock<(Redaction, RandomState)> = OnceLock::new();
+
+    #[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+    pub enum LogFormat {
+        /// Plain messages, prefixed with their level when it is not 'info'
+        Text,
+        /// One JSON object per line
+        Json,
+        /// Messages prefixed with their syslog priority, as journald expects
+        Journal,
+    }
+
+    struct Logger {
+        format: LogFormat,
+        level: LevelFilter,
+    }
+
+    /// Installs the logger, `verbosity` being the number of `-v` minus the number of `-q`.
+    pub fn init(verbosity: i8, format: Option<LogFormat>, redaction: Redaction) {
+        let level = match verbosity {
+            ..=-2 => LevelFilter::Error,
+            -1 => LevelFilter::Warn,
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            2.. => LevelFilter::Trace,
+        };
+        // systemd sets JOURNAL_STREAM when stderr is connected to the journal.
+        let format = format.unwrap_or_else(|| match std::env::var_os("JOURNAL_STREAM") {
+            Some(_) => LogFormat::Journal,
+            None => LogFormat::Text,
+        });
+        let redaction = match redaction {
+            Redaction::Auto if format == LogFormat::Journal || !std::io::stderr().is_terminal() => {
+                Redaction::Pseudonym
+            }
+            Redaction::Auto => Redaction::Off,
+            redaction => redaction,
+        };
+        let _ = REDACTION.set((redaction, RandomState::new()));
+        log::set_boxed_logger(Box::new(Logger { format, level }))
+            .expect("Failed to install the logger");
+        log::set_max_level(level);
+    }
+
+    impl Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            let line = match self.format {
+                LogFormat::Text => {
+                    let prefix = match record.level() {
+                        Level::Info => String::new(),
+                        Level::Warn => "warning: ".to_owned(),
+                        level => format!("{}: ", level.as_str().to_lowercase()),
+                    };
+                    format!("{prefix}{}{}", record.args(), text_fields(record))
+                }
+                LogFormat::Journal => {
+                    format!(
+                        "<{}>{}{}",
+                        priority(record.level()),
+                        record.args(),
+                        text_fields(record)
+                    )
+                }
+                LogFormat::Json => json_line(record),
+            };
+            let _ = writeln!(std::io::stderr().lock(), "{line}");
+        }
+
+        fn flush(&self) {}
+    }
+
+    pub fn redaction_enabled() -> bool {
+        REDACTION
+            .get()
+            .is_some_and(|(redaction, _)| *redaction != Redaction::Off)
+    }
+
+    /// Names a key for logging. Keys being debounced keep their name so that chatter
+    /// stays traceable, the others are redacted when redaction is enabled.
+    pub fn key_label(code: u16, debounced: bool) -> String {
+        label(REDACTION.get(), code, debounced)
+    }
+
+    fn label(redaction: Option<&(Redaction, RandomState)>, code: u16, debounced: bool) -> String {
+        match redaction {
+            Some((Redaction::Pseudonym, seed)) if !debounced => {
+                format!("key-{:08x}", seed.hash_one(code) as u32)
+            }
+            Some((Redaction::Class, _)) if !debounced => key_class(code).to_owned(),
+            _ => key_name(code),
+        }
+    }
+
+    /// Syslog priority of a level, see sd-daemon(3).
+    fn priority(level: Level) -> u8 {
+        match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    /// Renders structured fields as ` key=value` pairs.
+    fn text_fields(record: &Record) -> String {
+        struct Fields(String);
+        impl<'kvs> VisitSource<'kvs> for Fields {
+            fn visit_pair(
+                &mut self,
+                key: Key<'kvs>,
+                value: Value<'kvs>,
+            ) -> Result<(), log::kv::Error> {
+                self.0.push_str(&format!(" {key}={value}"));
+                Ok(())
+            }
+        }
+        let mut fields = Fields(String::new());
+        let _ = record.key_values().visit(&mut fields);
+        fields.0
+    }
+
+    fn json_line(record: &Record) -> String {
+        struct Fields(serde_json::Map<String, serde_json::Value>);
+        impl<'kvs> VisitSource<'kvs> for Fields {
+            fn visit_pair(
+                &mut self,
+                key: Key<'kvs>,
+                value: Value<'kvs>,
+            ) -> Result<(), log::kv::Error> {
+                let value = if let Some(value) = value.to_u64() {
+                    serde_json::Value::from(value)
+                } else if let Some(value) = value.to_i64() {
+                    serde_json::Value::from(value)
+                } else if let Some(value) = value.to_f64() {
+                    serde_json::Value::from(value)
+                } else if let Some(value) = value.to_bool() {
+                    serde_json::Value::from(value)
+                } else {
+                    serde_json::Value::from(value.to_string())
+                };
+                self.0.insert(key.to_string(), value);
+                Ok(())
+            }
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0.0, |t| t.as_secs_f64());
+        let mut fields = Fields(serde_json::Map::new());
+        fields.0.insert("timestamp".to_owned(), timestamp.into());
+        fields.0.insert(
+            "level".to_owned(),
+            record.level().as_str().to_lowercase().into(),
+        );
+        fields
+            .0
+            .insert("message".to_owned(), record.args().to_string().into());
+        let _ = record.key_values().visit(&mut fields);
+        serde_json::Value::Object(fields.0).to_string()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn renders_structured_fields() {
+            let fields: &[(&str, Value)] = &[
+                ("key", Value::from("KEY_A")),
+                ("interval_ms", Value::from(12u64)),
+            ];
+            let record = Record::builder()
+                .args(format_args!("Suppressed"))
+                .level(Level::Debug)
+                .key_values(&fields)
+                .build();
+            assert_eq!(text_fields(&record), " key=KEY_A interval_ms=12");
+
+            let json: serde_json::Value = serde_json::from_str(&json_line(&record)).unwrap();
+            assert_eq!(json["level"], "debug");
+            assert_eq!(json["message"], "Suppressed");
+            assert_eq!(json["key"], "KEY_A");
+            assert_eq!(json["interval_ms"], 12);
+            assert_eq!(priority(Level::Warn), 4);
+        }
+
+        #[test]
+        fn redacts_keys_not_debounced() {
+            const KEY_A: u16 = 30;
+            const KEY_S: u16 = 31;
+            let pseudonym = (Redaction::Pseudonym, RandomState::new());
+            let label_a = label(Some(&pseudonym), KEY_A, false);
+            assert!(label_a.starts_with("key-"));
+            assert_eq!(label(Some(&pseudonym), KEY_A, false), label_a);
+            assert_ne!(label(Some(&pseudonym), KEY_S, false), label_a);
+            assert_eq!(label(Some(&pseudonym), KEY_A, true), "KEY_A");
+
+            let class = (Redaction::Class, RandomState::new());
+            assert_eq!(label(Some(&class), KEY_A, false), "@letters");
+            assert_eq!(label(None, KEY_A, false), "KEY_A");
         }
     }
 }
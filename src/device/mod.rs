@@ -1,14 +1,18 @@
 #[cfg(target_os = "linux")]
 pub mod linux;
+#[cfg(all(target_os = "linux", feature = "dbus"))]
+pub use linux::dbus;
+#[cfg(all(target_os = "linux", feature = "metrics"))]
+pub use linux::metrics;
 #[cfg(target_os = "linux")]
-pub use linux::{command_line, config, debounce};
+pub use linux::{calibrate, command_line, config, control, debounce, logging, tester};
 
 #[cfg(target_os = "windows")]
 pub mod windows;
 #[cfg(target_os = "windows")]
 pub use windows::{
     command_line, config,
-    debounce::{run_message_loop, setup_windows_ll_keyboard_hook, KeyEventHolder},
+    debounce::{run_message_loop, setup_windows_ll_keyboard_hook},
 };
 
 pub use clap::Parser;
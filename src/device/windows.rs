@@ -1,22 +1,22 @@
 pub mod debounce {
-    use crate::device::windows::config::ConfigHolder;
-    use std::collections::HashMap;
+    use debounce_keyboard::config::Keymap;
+    use debounce_keyboard::engine::{
+        DebounceEngine, Decision, EngineConfig, KeyEvent, PRESS, RELEASE, REPEAT, WrappingMillis,
+    };
     use std::sync::{Mutex, OnceLock};
-    use std::time::Duration;
     use strum::{EnumIter, IntoEnumIterator};
 
-    use windows::core::Error;
     use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM};
     use windows::Win32::UI::WindowsAndMessaging::{
-        DispatchMessageA, GetMessageA, TranslateMessage, MSG,
+        DispatchMessageA, GetMessageA, MSG, TranslateMessage,
     };
+    use windows::core::Error;
 
     use windows::Win32::{UI::Input::KeyboardAndMouse::*, UI::WindowsAndMessaging::*};
 
-    static KEY_EVENT_HOLDER: OnceLock<Mutex<KeyEventHolder>> = OnceLock::new();
-    static CONFIG_HOLDER: OnceLock<ConfigHolder> = OnceLock::new();
+    static ENGINE: OnceLock<Mutex<DebounceEngine<MappedKey, WrappingMillis>>> = OnceLock::new();
 
-    #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, EnumIter)]
+    #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone, EnumIter)]
     #[allow(non_camel_case_types)]
     pub enum MappedKey {
         KEY_ESC,
@@ -243,116 +243,33 @@ pub mod debounce {
         MappedKey::iter().collect()
     }
 
-    #[derive(Debug)]
-    pub struct KeyEventHolder {
-        minimum_delay: Duration,
-        container: HashMap<u16, KeyEvent>,
-    }
-
-    impl KeyEventHolder {
-        pub fn new(minimum_delay: u64) -> KeyEventHolder {
-            KeyEventHolder {
-                minimum_delay: Duration::from_millis(minimum_delay),
-                container: HashMap::new(),
-            }
-        }
+    /// Names keys after their evdev equivalent, so config files work on both platforms.
+    pub struct WindowsKeys;
 
-        fn insert_event(&mut self, key_code: u16, key_event: KeyEvent) {
-            self.container.insert(key_code, key_event);
-        }
-
-        fn remove_event(&mut self, key_code: u16) {
-            self.container.remove(&key_code);
-        }
-
-        fn last_timestamp(&mut self, key_code: u16) -> Option<&mut KeyEvent> {
-            self.container.get_mut(&key_code)
-        }
-    }
-
-    #[derive(Debug)]
-    pub struct KeyEvent {
-        pub keycode: u16,
-        pub value: u32,
-        pub timestamp: Duration,
-        pub valid: bool,
-    }
+    impl Keymap for WindowsKeys {
+        type Key = MappedKey;
 
-    impl KeyEvent {
-        pub fn new(keycode: u16, value: u32, timestamp: Duration, valid: bool) -> KeyEvent {
-            KeyEvent {
-                keycode,
-                value,
-                timestamp,
-                valid,
+        /// Accepts the names of [`MappedKey`], or a virtual-key code such as `0x41`.
+        fn key(name: &str) -> Option<MappedKey> {
+            if let Some(key) = MappedKey::iter().find(|key| format!("{key:?}") == name) {
+                return Some(key);
             }
+            let code = match name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+                Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+                None => name.parse::<u16>().ok()?,
+            };
+            Some(MappedKey::from(code))
         }
-    }
 
-    fn should_skip(
-        key_code: u16,
-        key_value: u32,
-        timestamp: u32,
-        key_holder: &mut KeyEventHolder,
-        config_holder: &ConfigHolder,
-    ) -> bool {
-        if let Some(key_event) = key_holder.container.get(&key_code) {
-            if key_value == key_event.value
-                && (key_value == WM_KEYDOWN || key_value == WM_SYSKEYDOWN)
-                && key_event.valid
-            {
-                return false;
+        fn name(key: MappedKey) -> String {
+            match key {
+                MappedKey::KEY_OTHER(code) => format!("{code:#x}"),
+                key => format!("{key:?}"),
             }
         }
 
-        if !config_holder.keys.contains(&MappedKey::from(key_code)) {
-            return false;
-        }
-        let minimum_delay = key_holder.minimum_delay;
-        match key_holder.last_timestamp(key_code) {
-            Some(key_event) => {
-                let last_timestamp = Duration::from_millis(timestamp as u64);
-                let previous_timestamp = key_event.timestamp;
-
-                let time_expired =
-                    last_timestamp.saturating_sub(previous_timestamp) > minimum_delay;
-
-                if key_value == WM_KEYDOWN || key_value == WM_SYSKEYDOWN {
-                    if time_expired {
-                        key_event.valid = true;
-                        key_holder.insert_event(
-                            key_code,
-                            KeyEvent::new(
-                                key_code,
-                                key_value,
-                                Duration::from_millis(timestamp as u64),
-                                true,
-                            ),
-                        );
-                        return false;
-                    }
-                    return true;
-                } else if key_event.valid {
-                    key_event.valid = false;
-                    return false;
-                } else {
-                    return true;
-                }
-            }
-            None => {
-                if key_value == WM_KEYDOWN || key_value == WM_SYSKEYDOWN {
-                    key_holder.insert_event(
-                        key_code,
-                        KeyEvent::new(
-                            key_code,
-                            key_value,
-                            Duration::from_millis(timestamp as u64),
-                            true,
-                        ),
-                    );
-                }
-                false
-            }
+        fn all_keys() -> Vec<MappedKey> {
+            get_all_keys_code()
         }
     }
 
@@ -373,38 +290,88 @@ pub mod debounce {
     ) -> LRESULT {
         if n_code == HC_ACTION as i32 {
             let kbd_struct = &*(l_param.0 as *const KBDLLHOOKSTRUCT);
-            let vk_code = kbd_struct.vkCode;
-
-            if vk_code == VK_F8.0 as u32 || vk_code == VK_F9.0 as u32 {
-                CallNextHookEx(Some(HHOOK::default()), n_code, w_param, l_param);
+            if kbd_struct.dwExtraInfo == REPLAYED {
+                return CallNextHookEx(Some(HHOOK::default()), n_code, w_param, l_param);
             }
+            let key = MappedKey::from(kbd_struct.vkCode as u16);
+            let mut engine = ENGINE.get().unwrap().lock().unwrap();
+
+            // Windows repeats WM_KEYDOWN while a key is held instead of sending repeats.
+            let value = match w_param.0 as u32 {
+                WM_KEYDOWN | WM_SYSKEYDOWN if engine.is_held(key) => Some(REPEAT),
+                WM_KEYDOWN | WM_SYSKEYDOWN => Some(PRESS),
+                WM_KEYUP | WM_SYSKEYUP => Some(RELEASE),
+                _ => None,
+            };
+            if let Some(value) = value {
+                let event = KeyEvent::new(key, value, WrappingMillis(kbd_struct.time));
+                let decision = engine.process(event);
+                let deferred = engine.take_deferred();
+                drop(engine);
+                // Keys held back for the toggle chord have to reach applications before
+                // this one, so both are sent again in order.
+                if deferred
+                    .iter()
+                    .any(|&(_, decision)| decision == Decision::Pass)
+                {
+                    let replayed = deferred
+                        .into_iter()
+                        .chain([(event, decision)])
+                        .filter(|&(_, decision)| decision == Decision::Pass)
+                        .map(|(event, _)| event)
+                        .collect::<Vec<_>>();
+                    replay(&replayed);
+                    return LRESULT(1);
+                }
+                if decision != Decision::Pass {
+                    return LRESULT(1);
+                }
+            }
+        }
 
-            let mut key_event_holder = KEY_EVENT_HOLDER.get().unwrap().lock().unwrap();
-            let config_holder = CONFIG_HOLDER.get().unwrap();
+        CallNextHookEx(Some(HHOOK::default()), n_code, w_param, l_param)
+    }
 
-            let skip = should_skip(
-                kbd_struct.vkCode as u16,
-                w_param.0 as u32,
-                kbd_struct.time,
-                &mut key_event_holder,
-                &config_holder,
-            );
+    /// Marks the events sent by [`replay`], so that the hook lets them through.
+    const REPLAYED: usize = 0xDEB0;
 
-            if skip {
-                return LRESULT(1);
-            }
+    /// Sends key events again, as if they were typed.
+    fn replay(events: &[KeyEvent<MappedKey, WrappingMillis>]) {
+        let inputs = events
+            .iter()
+            .map(|event| INPUT {
+                r#type: INPUT_KEYBOARD,
+                Anonymous: INPUT_0 {
+                    ki: KEYBDINPUT {
+                        wVk: VIRTUAL_KEY(virtual_key(event.code)),
+                        dwFlags: match event.value {
+                            RELEASE => KEYEVENTF_KEYUP,
+                            _ => KEYBD_EVENT_FLAGS(0),
+                        },
+                        dwExtraInfo: REPLAYED,
+                        ..KEYBDINPUT::default()
+                    },
+                },
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            SendInput(&inputs, size_of::<INPUT>() as i32);
         }
+    }
 
-        CallNextHookEx(Some(HHOOK::default()), n_code, w_param, l_param)
+    /// Returns the virtual-key code a key is mapped from.
+    fn virtual_key(key: MappedKey) -> u16 {
+        match key {
+            MappedKey::KEY_OTHER(code) => code,
+            key => (0..=0xFF)
+                .find(|&code| MappedKey::from(code) == key)
+                .unwrap_or(0),
+        }
     }
 
-    pub fn setup_windows_ll_keyboard_hook(
-        key_event_holder: KeyEventHolder,
-        config_holder: ConfigHolder,
-    ) -> Result<(), Error> {
+    pub fn setup_windows_ll_keyboard_hook(config: EngineConfig<MappedKey>) -> Result<(), Error> {
         unsafe {
-            KEY_EVENT_HOLDER.get_or_init(|| Mutex::new(key_event_holder));
-            CONFIG_HOLDER.get_or_init(|| config_holder);
+            ENGINE.get_or_init(|| Mutex::new(DebounceEngine::new(config)));
 
             let h_instance = HINSTANCE::default();
             let mut hook =
@@ -433,98 +400,21 @@ pub mod command_line {
         long_about = "A utility designed to eliminate duplicate keystrokes by setting a delay between key presses when typing."
     )]
     pub struct Cli {
-        /// Path to the config file (if not provided, it will create a config file in the current directory or use an existing one)
+        /// Path to the config file (if not provided, config.ini in the current directory is used)
         #[arg(short, long, value_name = "path")]
         pub config_path: Option<PathBuf>,
     }
 }
 
 pub mod config {
-    use crate::device::windows::debounce::get_all_keys_code;
-    use crate::device::windows::debounce::MappedKey;
-    use config::{Config, File, FileFormat};
-    use std::collections::HashMap;
-    use std::fmt::{Display, Formatter};
-    use std::io::Write;
-    use std::path::PathBuf;
-    use std::sync::LazyLock;
-    use strum::IntoEnumIterator;
-    static AVAILABLE_KEYS: LazyLock<Vec<(String, MappedKey)>> = LazyLock::new(|| {
-        let mut keys: Vec<(String, MappedKey)> = Vec::new();
-        for key in MappedKey::iter() {
-            keys.push((format!("{key:?}"), key));
-        }
-        keys
-    });
-
-    pub struct ConfigHolder {
-        pub keys: Vec<MappedKey>,
-        pub delay_ms: u64,
-    }
-
-    impl Display for ConfigHolder {
-        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-            let keys = AVAILABLE_KEYS
-                .iter()
-                .filter(|(_, v)| self.keys.contains(v))
-                .map(|(k, _)| k.to_owned())
-                .collect::<Vec<_>>()
-                .join(",");
-            write!(f, "keys={keys}\ndelay_ms={}", self.delay_ms)
-        }
-    }
-
-    pub fn load_config(file: &PathBuf) -> ConfigHolder {
-        if !file.exists() {
-            std::fs::File::create(file).expect("Failed to create config file");
-        }
-        let file = File::new(file.to_str().unwrap(), FileFormat::Ini);
-
-        let settings = Config::builder().add_source(file).build().unwrap();
-
-        let settings = settings
-            .try_deserialize::<HashMap<String, String>>()
-            .unwrap();
-
-        let keys = match settings.get("keys") {
-            Some(keys) => get_keys_code(keys),
-            None => get_all_keys_code(),
-        };
-
-        let delay_ms = match settings.get("delay_ms") {
-            Some(value) => value.parse::<u64>().unwrap(),
-            None => 85,
-        };
+    use crate::device::windows::debounce::WindowsKeys;
+    use debounce_keyboard::config;
+    use debounce_keyboard::config::ConfigSources;
+    use std::path::Path;
 
-        ConfigHolder { keys, delay_ms }
-    }
-
-    pub fn save_config_to_path(path: &PathBuf, config: &ConfigHolder) {
-        let mut config_file = std::fs::File::create(path).expect("Failed to create config file");
-        config_file
-            .write_all(config.to_string().as_bytes())
-            .expect("Failed to write to config file");
-    }
+    pub type ConfigHolder = config::ConfigHolder<WindowsKeys>;
 
-    fn get_keys_code(keys: &str) -> Vec<MappedKey> {
-        let available_keys_map = AVAILABLE_KEYS
-            .iter()
-            .map(|(k, v)| (k.to_owned(), v.to_owned()))
-            .collect::<HashMap<String, MappedKey>>();
-        let keys_code = keys
-            .split(',')
-            .map(|k| (k, available_keys_map.get(k)))
-            .collect::<Vec<_>>();
-
-        if keys_code.iter().all(|(_, v)| v.is_some()) {
-            keys_code.into_iter().map(|(_, v)| *v.unwrap()).collect()
-        } else {
-            let invalid_keys = keys_code
-                .into_iter()
-                .filter(|&(_, v)| v.is_none())
-                .map(|(k, _)| k)
-                .collect::<Vec<_>>();
-            panic!("invalid key codes: {:?}", invalid_keys.join(","));
-        }
+    pub fn load_config(file: &Path) -> ConfigHolder {
+        config::load_config(&ConfigSources::single_file(file))
     }
 }
@@ -0,0 +1,1101 @@
+use crate::adaptive::{AdaptiveConfig, DelayLearner};
+use crate::bouncing::{BounceFinder, LearningConfig};
+use crate::chatter::{ChatterConfig, ChatterTracker};
+use crate::corrections::{Correction, CorrectionConfig, CorrectionDetector, CorrectionKind};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Event value of a key going up.
+pub const RELEASE: i32 = 0;
+/// Event value of a key going down.
+pub const PRESS: i32 = 1;
+/// Event value of a key held down long enough to auto-repeat.
+pub const REPEAT: i32 = 2;
+
+/// A point in time events are stamped with.
+pub trait Timestamp: Copy {
+    /// Returns the time elapsed since `earlier`, or `None` when `earlier` is later, as
+    /// happens when a wall clock is set back.
+    fn checked_duration_since(self, earlier: Self) -> Option<Duration>;
+}
+
+/// Time since any fixed origin, such as the timestamps of Linux input events.
+impl Timestamp for Duration {
+    fn checked_duration_since(self, earlier: Duration) -> Option<Duration> {
+        self.checked_sub(earlier)
+    }
+}
+
+impl Timestamp for Instant {
+    fn checked_duration_since(self, earlier: Instant) -> Option<Duration> {
+        Instant::checked_duration_since(&self, earlier)
+    }
+}
+
+/// A millisecond counter that wraps around every 49.7 days, such as the time of Windows
+/// keyboard hook events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrappingMillis(pub u32);
+
+impl Timestamp for WrappingMillis {
+    fn checked_duration_since(self, earlier: WrappingMillis) -> Option<Duration> {
+        Some(Duration::from_millis(self.0.wrapping_sub(earlier.0).into()))
+    }
+}
+
+/// A key event, with the values used by Linux input events. Keys are evdev key codes
+/// unless the platform uses another identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent<K = u16, T = Duration> {
+    /// Key code, as defined by `linux/input-event-codes.h` on Linux.
+    pub code: K,
+    /// [`RELEASE`], [`PRESS`] or [`REPEAT`].
+    pub value: i32,
+    /// When the event happened. Timestamps of one engine must come from the same clock,
+    /// ideally a monotonic one.
+    pub timestamp: T,
+}
+
+impl<K, T> KeyEvent<K, T> {
+    pub fn new(code: K, value: i32, timestamp: T) -> KeyEvent<K, T> {
+        KeyEvent {
+            code,
+            value,
+            timestamp,
+        }
+    }
+}
+
+/// What to do with an event given to [`DebounceEngine::process`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Emit the event.
+    Pass,
+    /// Drop the event.
+    Suppress,
+    /// Keep the event back, as it may be part of the toggle chord: it is decided again
+    /// and returned by [`DebounceEngine::take_deferred`] if the chord is not completed,
+    /// and dropped along with the chord otherwise.
+    Hold,
+}
+
+/// Settings of a [`DebounceEngine`], which can be replaced at any time.
+#[derive(Debug, Clone)]
+pub struct EngineConfig<K = u16> {
+    /// Minimum time between two presses of the same key.
+    pub delay: Duration,
+    /// Delays of keys that need their own, replacing [`EngineConfig::delay`].
+    pub key_delays: HashMap<K, Duration>,
+    /// Keys to debounce, every other key is always passed. With
+    /// [`EngineConfig::learn_keys`], only those found bouncing among them are debounced
+    /// once learning is over.
+    pub keys: HashSet<K>,
+    /// Keys that pause or resume filtering when held together, empty when disabled.
+    pub toggle_hotkey: Vec<K>,
+    /// Key that disables filtering while it is held.
+    pub bypass_key: Option<K>,
+    /// Bounds of the delays learned for debounced keys without a delay of their own, which
+    /// replace [`EngineConfig::delay`] once learned. Delays are not learned when `None`.
+    pub adaptive: Option<AdaptiveConfig>,
+    /// How to look for the user's corrections, and how much they move the delays of the
+    /// keys they correct. Corrections are not looked for when `None`.
+    pub correction: Option<CorrectionConfig<K>>,
+    /// How to find the keys that bounce, to debounce only those. Every key listed is
+    /// debounced when `None`.
+    pub learn_keys: Option<LearningConfig>,
+    /// How to lengthen the delay of keys while they chatter. Delays stay as they are when
+    /// `None`.
+    pub chatter: Option<ChatterConfig>,
+}
+
+impl<K> Default for EngineConfig<K> {
+    fn default() -> EngineConfig<K> {
+        EngineConfig {
+            delay: Duration::ZERO,
+            key_delays: HashMap::new(),
+            keys: HashSet::new(),
+            toggle_hotkey: Vec::new(),
+            bypass_key: None,
+            adaptive: None,
+            correction: None,
+            learn_keys: None,
+            chatter: None,
+        }
+    }
+}
+
+impl<K: Eq + Hash> EngineConfig<K> {
+    /// Returns the minimum time between two presses of `key`.
+    pub fn delay_of(&self, key: &K) -> Duration {
+        self.key_delays.get(key).copied().unwrap_or(self.delay)
+    }
+}
+
+#[derive(Debug)]
+struct KeyState<T> {
+    /// When the last press was passed.
+    last_press: T,
+    /// Whether the last press was passed and its release has not been yet.
+    down: bool,
+}
+
+/// The debounce state machine.
+///
+/// A press is suppressed when it comes less than the key's delay, see
+/// [`DebounceEngine::delay_of`], after the last press passed for the same key, and so are
+/// the repeats and the release that follow it.
+/// Keys not listed in [`EngineConfig::keys`] are always passed. Whatever the settings, a
+/// release or repeat is never passed for a key whose press was not.
+///
+/// Keys are identified by `K`, evdev key codes by default, and events are stamped with
+/// `T`, any [`Timestamp`].
+#[derive(Debug)]
+pub struct DebounceEngine<K = u16, T = Duration> {
+    config: EngineConfig<K>,
+    keys: HashMap<K, KeyState<T>>,
+    paused: bool,
+    /// Keys physically held down, used to detect the toggle chord and bypass key.
+    held: HashSet<K>,
+    /// Keys whose press was passed and release was not, as seen downstream.
+    emitted: HashSet<K>,
+    /// Events of the toggle chord keys held back until the chord is completed or not.
+    pending: Vec<KeyEvent<K, T>>,
+    /// Events held back that turned out not to start the chord, with their decision.
+    deferred: Vec<(KeyEvent<K, T>, Decision)>,
+    /// Keys of the toggle chord that were never emitted, swallowed until released.
+    swallowed: HashSet<K>,
+    /// Learns the delays of debounced keys, when [`EngineConfig::adaptive`] is set.
+    learner: Option<DelayLearner<K, T>>,
+    /// Looks for corrections, when [`EngineConfig::correction`] is set.
+    detector: Option<CorrectionDetector<K, T>>,
+    /// Corrections found since [`DebounceEngine::take_corrections`] was last called.
+    corrections: Vec<Correction<K>>,
+    /// Milliseconds added to the delay of keys by corrections, negative when removed.
+    delay_corrections: HashMap<K, i64>,
+    /// Finds the keys that bounce, when [`EngineConfig::learn_keys`] is set.
+    finder: Option<BounceFinder<K, T>>,
+    /// Tracks the keys that chatter, when [`EngineConfig::chatter`] is set.
+    chatter: Option<ChatterTracker<K, T>>,
+}
+
+impl<K, T> DebounceEngine<K, T>
+where
+    K: Copy + Eq + Hash + Ord,
+    T: Timestamp,
+{
+    pub fn new(config: EngineConfig<K>) -> DebounceEngine<K, T> {
+        DebounceEngine {
+            learner: config.adaptive.map(DelayLearner::new),
+            detector: config
+                .correction
+                .map(|correction| CorrectionDetector::new(correction.undo_key)),
+            corrections: Vec::new(),
+            delay_corrections: HashMap::new(),
+            finder: config.learn_keys.map(BounceFinder::new),
+            chatter: config.chatter.map(ChatterTracker::new),
+            config,
+            keys: HashMap::new(),
+            paused: false,
+            held: HashSet::new(),
+            emitted: HashSet::new(),
+            pending: Vec::new(),
+            deferred: Vec::new(),
+            swallowed: HashSet::new(),
+        }
+    }
+
+    pub fn config(&self) -> &EngineConfig<K> {
+        &self.config
+    }
+
+    /// Replaces the settings, keeping track of the keys currently held and of the delays
+    /// learned so far while learning stays on.
+    pub fn set_config(&mut self, config: EngineConfig<K>) {
+        match (config.adaptive, &mut self.learner) {
+            (Some(adaptive), Some(learner)) => learner.set_config(adaptive),
+            (adaptive, learner) => *learner = adaptive.map(DelayLearner::new),
+        }
+        match (config.learn_keys, &mut self.finder) {
+            (Some(learn_keys), Some(finder)) => finder.set_config(learn_keys),
+            (learn_keys, finder) => *finder = learn_keys.map(BounceFinder::new),
+        }
+        match (config.chatter, &mut self.chatter) {
+            (Some(chatter), Some(tracker)) => tracker.set_config(chatter),
+            (chatter, tracker) => *tracker = chatter.map(ChatterTracker::new),
+        }
+        let undo_key = |config: &EngineConfig<K>| config.correction.map(|c| c.undo_key);
+        if undo_key(&config) != undo_key(&self.config) {
+            self.detector = undo_key(&config).map(CorrectionDetector::new);
+        }
+        self.config = config;
+    }
+
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.config.delay = delay;
+    }
+
+    pub fn set_keys(&mut self, keys: impl IntoIterator<Item = K>) {
+        self.config.keys = keys.into_iter().collect();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Turns filtering off or back on, as the toggle hotkey does.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Returns whether a key is physically held down, whatever was decided for its press.
+    pub fn is_held(&self, code: K) -> bool {
+        self.held.contains(&code)
+    }
+
+    /// Returns the minimum time between two presses of `key`: its own delay if it has
+    /// one, else the delay learned for it if any, else [`EngineConfig::delay`], moved by
+    /// the corrections of the key within their bounds, and lengthened while it chatters.
+    pub fn delay_of(&self, key: &K) -> Duration {
+        let learned = self.learner.as_ref().and_then(|learner| learner.delay(key));
+        let delay = match (self.config.key_delays.get(key), learned) {
+            (Some(&delay), _) | (None, Some(delay)) => delay,
+            (None, None) => self.config.delay,
+        };
+        let delay = match (self.config.correction, self.delay_corrections.get(key)) {
+            (Some(correction), Some(&ms)) => {
+                let moved = Duration::from_millis(ms.unsigned_abs());
+                let delay = match ms < 0 {
+                    true => delay.saturating_sub(moved),
+                    false => delay + moved,
+                };
+                delay.min(correction.ceiling).max(correction.floor)
+            }
+            _ => delay,
+        };
+        match &self.chatter {
+            Some(chatter) => chatter.delay(key, delay),
+            None => delay,
+        }
+    }
+
+    /// Returns the delay of every debounced key pressed so far, see
+    /// [`DebounceEngine::delay_of`].
+    pub fn delays(&self) -> BTreeMap<K, Duration> {
+        self.keys
+            .keys()
+            .filter(|key| self.debounces(key))
+            .map(|&key| (key, self.delay_of(&key)))
+            .collect()
+    }
+
+    /// Returns whether the delay of `key` is lengthened because it chatters.
+    pub fn is_chattering(&self, key: &K) -> bool {
+        self.chatter
+            .as_ref()
+            .is_some_and(|chatter| chatter.is_chattering(key))
+    }
+
+    /// Returns whether presses of `key` are debounced.
+    pub fn debounces(&self, key: &K) -> bool {
+        self.config.keys.contains(key)
+            && self
+                .finder
+                .as_ref()
+                .is_none_or(|finder| finder.needs_debouncing(key))
+    }
+
+    /// Returns what was found about the keys that bounce, when looking for them.
+    pub fn bounce_finder(&self) -> Option<&BounceFinder<K, T>> {
+        self.finder.as_ref()
+    }
+
+    /// Takes up the keys found bouncing earlier and the number of presses seen then, as by
+    /// a previous run, when looking for the keys that bounce.
+    pub fn restore_bouncing_keys(&mut self, keys: impl IntoIterator<Item = K>, presses: u64) {
+        if let Some(finder) = &mut self.finder {
+            finder.restore(keys, presses);
+        }
+    }
+
+    /// Returns the corrections found since the last call, in order.
+    pub fn take_corrections(&mut self) -> Vec<Correction<K>> {
+        std::mem::take(&mut self.corrections)
+    }
+
+    /// Returns the milliseconds corrections added to the delay of keys, negative when they
+    /// removed some.
+    pub fn delay_corrections(&self) -> BTreeMap<K, i64> {
+        self.delay_corrections
+            .iter()
+            .filter(|(_, ms)| **ms != 0)
+            .map(|(&key, &ms)| (key, ms))
+            .collect()
+    }
+
+    /// Takes up the corrections of delays made earlier, as by a previous run.
+    pub fn restore_delay_corrections(&mut self, corrections: impl IntoIterator<Item = (K, i64)>) {
+        self.delay_corrections.extend(corrections);
+    }
+
+    /// Returns the delays learned so far, empty when delays are not learned.
+    pub fn learned_delays(&self) -> BTreeMap<K, Duration> {
+        self.learner
+            .as_ref()
+            .map(|learner| learner.learned().clone())
+            .unwrap_or_default()
+    }
+
+    /// Takes up delays learned earlier, as by a previous run, when delays are learned.
+    pub fn restore_learned_delays(&mut self, delays: impl IntoIterator<Item = (K, Duration)>) {
+        if let Some(learner) = &mut self.learner {
+            learner.restore(delays);
+        }
+    }
+
+    /// Returns when the last press of a key was passed.
+    pub fn last_press(&self, code: K) -> Option<T> {
+        self.keys.get(&code).map(|state| state.last_press)
+    }
+
+    /// Decides whether to emit an event. Events have to be given in the order they
+    /// happened, and the ones returned by [`DebounceEngine::take_deferred`] emitted
+    /// before this one.
+    pub fn process(&mut self, event: KeyEvent<K, T>) -> Decision {
+        // Delays and bouncing keys are learned from what the keyboard sends, bounces
+        // included.
+        if self.config.keys.contains(&event.code) {
+            if let Some(learner) = &mut self.learner {
+                learner.observe(&event);
+            }
+            if let Some(finder) = &mut self.finder {
+                finder.observe(&event);
+            }
+        }
+        if let Some(chatter) = &mut self.chatter {
+            chatter.calm_down(event.timestamp);
+        }
+        match event.value {
+            PRESS => self.held.insert(event.code),
+            RELEASE => self.held.remove(&event.code),
+            _ => false,
+        };
+        self.handle_chord(&event)
+            .unwrap_or_else(|| self.decide(&event))
+    }
+
+    /// Returns the events held back by earlier calls to [`DebounceEngine::process`] that
+    /// turned out not to be part of the toggle chord, in order, along with what was
+    /// decided for them now. They come before the event given to the last call.
+    pub fn take_deferred(&mut self) -> Vec<(KeyEvent<K, T>, Decision)> {
+        std::mem::take(&mut self.deferred)
+    }
+
+    /// Returns the deferred emissions owed when the input goes away, such as a device
+    /// being unplugged: a release for every key passed down and not released yet.
+    /// Events held back for the toggle chord are dropped.
+    pub fn release_held(&mut self, timestamp: T) -> Vec<KeyEvent<K, T>> {
+        self.held.clear();
+        self.pending.clear();
+        self.deferred.clear();
+        self.swallowed.clear();
+        for state in self.keys.values_mut() {
+            state.down = false;
+        }
+        let mut codes = self.emitted.drain().collect::<Vec<_>>();
+        codes.sort_unstable();
+        codes
+            .into_iter()
+            .map(|code| KeyEvent::new(code, RELEASE, timestamp))
+            .collect()
+    }
+
+    /// Holds back the presses of the toggle chord keys while another one and nothing
+    /// else is held, so that a completed chord reaches nothing downstream but the key it
+    /// started with. A lone chord key, such as a modifier, is not held back. Returns
+    /// `None` when the event is to be decided as usual, after deciding for the events held
+    /// back so far unless it repeats the key the chord started with.
+    fn handle_chord(&mut self, event: &KeyEvent<K, T>) -> Option<Decision> {
+        if self.swallowed.contains(&event.code) {
+            if event.value == RELEASE {
+                self.swallowed.remove(&event.code);
+            }
+            return Some(Decision::Suppress);
+        }
+        let chord = &self.config.toggle_hotkey;
+        if event.value == PRESS
+            && chord.contains(&event.code)
+            && self.held.iter().all(|key| chord.contains(key))
+        {
+            if chord.iter().all(|key| self.held.contains(key)) {
+                self.paused = !self.paused;
+                self.pending.clear();
+                // Chord keys passed before the chord started keep their release.
+                self.swallowed = chord
+                    .iter()
+                    .filter(|key| !self.emitted.contains(key))
+                    .copied()
+                    .collect();
+                return Some(Decision::Hold);
+            }
+            if self.held.len() > 1 {
+                self.pending.push(*event);
+                return Some(Decision::Hold);
+            }
+        }
+        if event.value == REPEAT && !self.pending.is_empty() && chord.contains(&event.code) {
+            if self.pending.iter().any(|held| held.code == event.code) {
+                self.pending.push(*event);
+                return Some(Decision::Hold);
+            }
+            // The key the chord started with was sent, and so are its repeats.
+            return None;
+        }
+        for held in std::mem::take(&mut self.pending) {
+            let decision = self.decide(&held);
+            self.deferred.push((held, decision));
+        }
+        None
+    }
+
+    /// Decides for an event that is not part of the toggle chord.
+    fn decide(&mut self, event: &KeyEvent<K, T>) -> Decision {
+        let mut decision = self
+            .handle_bypass(event)
+            .unwrap_or_else(|| self.filter(event));
+        // A key downstream sees up, because its press was suppressed or came before the
+        // engine started, must not be released or repeated there.
+        if event.value != PRESS && !self.emitted.contains(&event.code) {
+            decision = Decision::Suppress;
+        }
+        if decision == Decision::Pass {
+            match event.value {
+                PRESS => self.emitted.insert(event.code),
+                RELEASE => self.emitted.remove(&event.code),
+                _ => false,
+            };
+        }
+        if let Some(chatter) = &mut self.chatter
+            && event.value == PRESS
+            && decision == Decision::Suppress
+        {
+            chatter.record_suppressed(event.code, event.timestamp);
+        }
+        self.look_for_correction(event, decision);
+        decision
+    }
+
+    /// Records the correction an event makes, if any, moving the delay of the key it
+    /// corrects by a step: up for a missed bounce, down for a false positive.
+    fn look_for_correction(&mut self, event: &KeyEvent<K, T>, decision: Decision) {
+        let delay = self.delay_of(&event.code);
+        let Some(detector) = &mut self.detector else {
+            return;
+        };
+        let Some(correction) = detector.observe(event, decision == Decision::Pass, delay) else {
+            return;
+        };
+        self.corrections.push(correction);
+        let Some(config) = self.config.correction else {
+            return;
+        };
+        if config.step.is_zero() || !self.debounces(&correction.key) {
+            return;
+        }
+        let delay = self.delay_of(&correction.key);
+        let step = config.step.as_millis() as i64;
+        let step = match correction.kind {
+            CorrectionKind::MissedBounce if delay < config.ceiling => step,
+            CorrectionKind::FalsePositive if delay > config.floor => -step,
+            _ => return,
+        };
+        *self.delay_corrections.entry(correction.key).or_default() += step;
+    }
+
+    /// Passes every event while paused or while the bypass key is held, returning `None`
+    /// when the event has to go through the debounce filter.
+    fn handle_bypass(&mut self, event: &KeyEvent<K, T>) -> Option<Decision> {
+        let bypass = self
+            .config
+            .bypass_key
+            .is_some_and(|key| self.held.contains(&key));
+        if !self.paused && !bypass {
+            return None;
+        }
+        // Events passed are still recorded, so that filtering picks up from the right
+        // key state once it is back on.
+        self.record_passed(event);
+        Some(Decision::Pass)
+    }
+
+    /// Records an event passed without filtering as if filtering had passed it.
+    fn record_passed(&mut self, event: &KeyEvent<K, T>) {
+        match event.value {
+            PRESS => {
+                self.keys.insert(
+                    event.code,
+                    KeyState {
+                        last_press: event.timestamp,
+                        down: true,
+                    },
+                );
+            }
+            RELEASE => {
+                if let Some(state) = self.keys.get_mut(&event.code) {
+                    state.down = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn filter(&mut self, event: &KeyEvent<K, T>) -> Decision {
+        if event.value == REPEAT || !self.config.keys.contains(&event.code) {
+            return Decision::Pass;
+        }
+        // Keys that may turn out to bounce are recorded, so that filtering them picks up
+        // from the right key state.
+        if !self.debounces(&event.code) {
+            self.record_passed(event);
+            return Decision::Pass;
+        }
+        let delay = self.delay_of(&event.code);
+        match self.keys.get_mut(&event.code) {
+            Some(state) if event.value == PRESS => {
+                // A press stamped before the last one means the clock went back: the last
+                // press is then treated as long gone rather than eating every press until
+                // the clock catches up.
+                let elapsed = event.timestamp.checked_duration_since(state.last_press);
+                if elapsed.is_none_or(|elapsed| elapsed > delay) {
+                    state.last_press = event.timestamp;
+                    state.down = true;
+                    Decision::Pass
+                } else {
+                    Decision::Suppress
+                }
+            }
+            Some(state) if state.down => {
+                state.down = false;
+                Decision::Pass
+            }
+            Some(_) => Decision::Suppress,
+            None => {
+                if event.value == PRESS {
+                    self.keys.insert(
+                        event.code,
+                        KeyState {
+                            last_press: event.timestamp,
+                            down: true,
+                        },
+                    );
+                }
+                Decision::Pass
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: u16 = 30;
+    const KEY_C: u16 = 46;
+    const KEY_LEFTCTRL: u16 = 29;
+    const KEY_LEFTALT: u16 = 56;
+    const KEY_PAUSE: u16 = 119;
+    const KEY_RIGHTALT: u16 = 100;
+
+    fn engine() -> DebounceEngine {
+        DebounceEngine::new(EngineConfig {
+            delay: Duration::from_millis(50),
+            key_delays: HashMap::new(),
+            keys: HashSet::from([KEY_A, KEY_C]),
+            toggle_hotkey: vec![KEY_LEFTCTRL, KEY_LEFTALT, KEY_PAUSE],
+            bypass_key: Some(KEY_RIGHTALT),
+            adaptive: None,
+            correction: None,
+            learn_keys: None,
+            chatter: None,
+        })
+    }
+
+    /// Feeds `(code, value, milliseconds)` events and returns the ones emitted, in order.
+    fn emitted(engine: &mut DebounceEngine, events: &[(u16, i32, u64)]) -> Vec<(u16, i32, u64)> {
+        let mut emitted = Vec::new();
+        for &(code, value, ms) in events {
+            let event = KeyEvent::new(code, value, Duration::from_millis(ms));
+            let decision = engine.process(event);
+            let deferred = engine
+                .take_deferred()
+                .into_iter()
+                .chain([(event, decision)]);
+            for (event, decision) in deferred {
+                if decision == Decision::Pass {
+                    let ms = event.timestamp.as_millis() as u64;
+                    emitted.push((event.code, event.value, ms));
+                }
+            }
+        }
+        emitted
+    }
+
+    #[test]
+    fn suppresses_presses_within_delay_with_their_release() {
+        let mut engine = engine();
+        let events = [
+            (KEY_A, PRESS, 0),
+            (KEY_A, RELEASE, 10),
+            (KEY_A, PRESS, 20),
+            (KEY_A, RELEASE, 30),
+            (KEY_A, PRESS, 100),
+        ];
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [(KEY_A, PRESS, 0), (KEY_A, RELEASE, 10), (KEY_A, PRESS, 100)]
+        );
+    }
+
+    #[test]
+    fn repeats_follow_their_press() {
+        let mut engine = engine();
+        let events = [
+            (KEY_A, PRESS, 0),
+            (KEY_A, REPEAT, 5),
+            (KEY_A, RELEASE, 10),
+            (KEY_A, PRESS, 20),
+            (KEY_A, REPEAT, 500),
+            (KEY_A, RELEASE, 530),
+            (KEY_C, REPEAT, 540),
+            (KEY_C, RELEASE, 550),
+        ];
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [(KEY_A, PRESS, 0), (KEY_A, REPEAT, 5), (KEY_A, RELEASE, 10)]
+        );
+    }
+
+    #[test]
+    fn key_delays_replace_the_delay() {
+        let mut engine = engine();
+        let mut config = engine.config().clone();
+        config.key_delays.insert(KEY_C, Duration::from_millis(10));
+        engine.set_config(config);
+        let events = [
+            (KEY_A, PRESS, 0),
+            (KEY_A, RELEASE, 5),
+            (KEY_A, PRESS, 20),
+            (KEY_A, RELEASE, 25),
+            (KEY_C, PRESS, 30),
+            (KEY_C, RELEASE, 35),
+            (KEY_C, PRESS, 50),
+            (KEY_C, RELEASE, 55),
+        ];
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [
+                (KEY_A, PRESS, 0),
+                (KEY_A, RELEASE, 5),
+                (KEY_C, PRESS, 30),
+                (KEY_C, RELEASE, 35),
+                (KEY_C, PRESS, 50),
+                (KEY_C, RELEASE, 55),
+            ]
+        );
+    }
+
+    #[test]
+    fn learned_delays_replace_the_delay() {
+        let mut engine = engine();
+        let mut config = engine.config().clone();
+        config.adaptive = Some(AdaptiveConfig {
+            floor: Duration::from_millis(10),
+            ceiling: Duration::from_millis(150),
+        });
+        config.key_delays.insert(KEY_C, Duration::from_millis(60));
+        engine.set_config(config);
+        // A key bouncing 5ms after every press gets a delay between 5ms and 145ms.
+        let chatter = (0..=crate::adaptive::MIN_PRESSES as u64 / 2).flat_map(|i| {
+            let at = i * 150;
+            [
+                (KEY_A, PRESS, at),
+                (KEY_A, RELEASE, at + 3),
+                (KEY_A, PRESS, at + 5),
+                (KEY_A, RELEASE, at + 8),
+            ]
+        });
+        emitted(&mut engine, &chatter.collect::<Vec<_>>());
+        assert_eq!(engine.delay_of(&KEY_A), Duration::from_millis(27));
+        let events = [
+            (KEY_A, PRESS, 2000),
+            (KEY_A, RELEASE, 2010),
+            (KEY_A, PRESS, 2040),
+        ];
+        assert_eq!(emitted(&mut engine, &events), events);
+
+        engine.restore_learned_delays([(KEY_C, Duration::from_millis(20))]);
+        assert_eq!(engine.delay_of(&KEY_C), Duration::from_millis(60));
+        let config = engine.config().clone();
+        engine.set_config(config);
+        assert_eq!(engine.learned_delays().len(), 2);
+
+        let mut config = engine.config().clone();
+        config.adaptive = None;
+        engine.set_config(config);
+        assert_eq!(engine.delay_of(&KEY_A), Duration::from_millis(50));
+        assert!(engine.learned_delays().is_empty());
+    }
+
+    #[test]
+    fn corrections_move_delays_within_bounds() {
+        const KEY_BACKSPACE: u16 = 14;
+        let mut engine = engine();
+        let mut config = engine.config().clone();
+        config.correction = Some(CorrectionConfig {
+            undo_key: KEY_BACKSPACE,
+            step: Duration::from_millis(10),
+            floor: Duration::from_millis(40),
+            ceiling: Duration::from_millis(60),
+        });
+        engine.set_config(config);
+        // Bounces get through and are deleted, the second one once the delay is as long
+        // as it gets.
+        for (at, bounce) in [(0, 55), (1000, 65)] {
+            let events = [
+                (KEY_A, PRESS, at),
+                (KEY_A, RELEASE, at + 50),
+                (KEY_A, PRESS, at + bounce),
+                (KEY_A, RELEASE, at + bounce + 5),
+                (KEY_BACKSPACE, PRESS, at + 300),
+                (KEY_BACKSPACE, RELEASE, at + 350),
+            ];
+            emitted(&mut engine, &events);
+        }
+        let missed = Correction {
+            key: KEY_A,
+            kind: CorrectionKind::MissedBounce,
+        };
+        assert_eq!(engine.take_corrections(), [missed, missed]);
+        assert_eq!(engine.delay_of(&KEY_A), Duration::from_millis(60));
+        assert_eq!(engine.delay_corrections(), BTreeMap::from([(KEY_A, 10)]));
+
+        // The first bounce is now suppressed.
+        let events = [
+            (KEY_A, PRESS, 2000),
+            (KEY_A, RELEASE, 2050),
+            (KEY_A, PRESS, 2055),
+        ];
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [(KEY_A, PRESS, 2000), (KEY_A, RELEASE, 2050)]
+        );
+        assert_eq!(engine.take_corrections(), []);
+
+        engine.restore_delay_corrections([(KEY_C, -50)]);
+        assert_eq!(engine.delay_of(&KEY_C), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn learning_restricts_debouncing_to_keys_that_bounce() {
+        let mut engine = engine();
+        let mut config = engine.config().clone();
+        config.learn_keys = Some(LearningConfig {
+            presses: 5,
+            bounces: 2,
+        });
+        engine.set_config(config);
+        let events = [
+            (KEY_A, PRESS, 0),
+            (KEY_A, RELEASE, 10),
+            (KEY_A, PRESS, 15),
+            (KEY_A, RELEASE, 20),
+            (KEY_C, PRESS, 100),
+            (KEY_C, RELEASE, 110),
+            (KEY_C, PRESS, 150),
+            (KEY_C, RELEASE, 160),
+            (KEY_C, PRESS, 300),
+            (KEY_C, RELEASE, 310),
+        ];
+        // Every key is debounced while learning.
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [
+                (KEY_A, PRESS, 0),
+                (KEY_A, RELEASE, 10),
+                (KEY_C, PRESS, 100),
+                (KEY_C, RELEASE, 110),
+                (KEY_C, PRESS, 300),
+                (KEY_C, RELEASE, 310),
+            ]
+        );
+        assert!(!engine.bounce_finder().unwrap().is_learning());
+        assert!(!engine.debounces(&KEY_A));
+
+        // KEY_A bounces a second time, and is debounced from that bounce on.
+        let events = [
+            (KEY_A, PRESS, 1000),
+            (KEY_A, RELEASE, 1010),
+            (KEY_A, PRESS, 1015),
+            (KEY_A, RELEASE, 1020),
+            (KEY_A, PRESS, 1040),
+            (KEY_C, PRESS, 1100),
+            (KEY_C, RELEASE, 1110),
+            (KEY_C, PRESS, 1120),
+        ];
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [
+                (KEY_A, PRESS, 1000),
+                (KEY_A, RELEASE, 1010),
+                (KEY_C, PRESS, 1100),
+                (KEY_C, RELEASE, 1110),
+                (KEY_C, PRESS, 1120),
+            ]
+        );
+        assert_eq!(
+            engine.bounce_finder().unwrap().found(),
+            &std::collections::BTreeSet::from([KEY_A])
+        );
+        assert!(engine.debounces(&KEY_A));
+    }
+
+    #[test]
+    fn chattering_keys_get_a_longer_delay_until_quiet() {
+        let mut engine = engine();
+        let mut config = engine.config().clone();
+        config.chatter = Some(ChatterConfig {
+            bounces: 2,
+            window: Duration::from_millis(1000),
+            factor: 2.0,
+            quiet: Duration::from_millis(2000),
+        });
+        engine.set_config(config);
+        let events = [
+            (KEY_A, PRESS, 0),
+            (KEY_A, RELEASE, 5),
+            (KEY_A, PRESS, 10),
+            (KEY_A, RELEASE, 15),
+            (KEY_A, PRESS, 30),
+            (KEY_A, RELEASE, 35),
+            // Passed with the usual delay, but KEY_A chatters.
+            (KEY_A, PRESS, 90),
+            (KEY_A, RELEASE, 95),
+            (KEY_A, PRESS, 200),
+            (KEY_A, RELEASE, 210),
+        ];
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [
+                (KEY_A, PRESS, 0),
+                (KEY_A, RELEASE, 5),
+                (KEY_A, PRESS, 200),
+                (KEY_A, RELEASE, 210),
+            ]
+        );
+        assert!(engine.is_chattering(&KEY_A));
+        assert_eq!(
+            engine.delays(),
+            BTreeMap::from([(KEY_A, Duration::from_millis(100))])
+        );
+
+        let events = [
+            (KEY_A, PRESS, 2100),
+            (KEY_A, RELEASE, 2105),
+            (KEY_A, PRESS, 2170),
+        ];
+        assert_eq!(emitted(&mut engine, &events), events);
+        assert!(!engine.is_chattering(&KEY_A));
+        assert_eq!(engine.delay_of(&KEY_A), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn passes_keys_not_debounced() {
+        let mut engine = engine();
+        let events = [
+            (KEY_PAUSE + 1, PRESS, 0),
+            (KEY_PAUSE + 1, RELEASE, 1),
+            (KEY_PAUSE + 1, PRESS, 2),
+        ];
+        assert_eq!(emitted(&mut engine, &events), events);
+    }
+
+    #[test]
+    fn clock_set_back_expires_last_press() {
+        let mut engine = engine();
+        let events = [
+            (KEY_A, PRESS, 100_000),
+            (KEY_A, RELEASE, 100_010),
+            (KEY_A, PRESS, 0),
+            (KEY_A, RELEASE, 5),
+        ];
+        assert_eq!(emitted(&mut engine, &events), events);
+        assert_eq!(engine.last_press(KEY_A), Some(Duration::ZERO));
+        assert_eq!(emitted(&mut engine, &[(KEY_A, PRESS, 20)]), []);
+    }
+
+    #[test]
+    fn wrapping_millis_survive_wrap_around() {
+        let mut engine = DebounceEngine::<u16, WrappingMillis>::new(engine().config().clone());
+        let at = |ms: u32| (u32::MAX - 10).wrapping_add(ms);
+        let events = [
+            (0, PRESS),
+            (5, RELEASE),
+            (20, PRESS),
+            (25, RELEASE),
+            (60, PRESS),
+        ];
+        let decisions = events
+            .map(|(ms, value)| engine.process(KeyEvent::new(KEY_A, value, WrappingMillis(at(ms)))));
+        assert_eq!(
+            decisions,
+            [
+                Decision::Pass,
+                Decision::Pass,
+                Decision::Suppress,
+                Decision::Suppress,
+                Decision::Pass
+            ]
+        );
+    }
+
+    #[test]
+    fn chord_toggles_filtering_without_leaking_keys() {
+        let mut engine = engine();
+        let chord = [
+            (KEY_LEFTCTRL, PRESS, 0),
+            (KEY_LEFTALT, PRESS, 10),
+            (KEY_LEFTCTRL, REPEAT, 15),
+            (KEY_PAUSE, PRESS, 20),
+            (KEY_PAUSE, RELEASE, 30),
+            (KEY_LEFTALT, RELEASE, 40),
+            (KEY_LEFTCTRL, RELEASE, 50),
+        ];
+        // The key the chord starts with is sent right away, and so is its release.
+        let ctrl = [
+            (KEY_LEFTCTRL, PRESS, 0),
+            (KEY_LEFTCTRL, REPEAT, 15),
+            (KEY_LEFTCTRL, RELEASE, 50),
+        ];
+        assert_eq!(emitted(&mut engine, &chord), ctrl);
+        assert!(engine.is_paused());
+        let chatter = [
+            (KEY_A, PRESS, 100),
+            (KEY_A, RELEASE, 105),
+            (KEY_A, PRESS, 110),
+            (KEY_A, RELEASE, 115),
+        ];
+        assert_eq!(emitted(&mut engine, &chatter), chatter);
+
+        let chord = chord.map(|(code, value, ms)| (code, value, ms + 1000));
+        let ctrl = ctrl.map(|(code, value, ms)| (code, value, ms + 1000));
+        assert_eq!(emitted(&mut engine, &chord), ctrl);
+        assert!(!engine.is_paused());
+    }
+
+    #[test]
+    fn lone_chord_key_is_not_held_back() {
+        let mut engine = engine();
+        let press = KeyEvent::new(KEY_LEFTCTRL, PRESS, Duration::from_millis(0));
+        assert_eq!(engine.process(press), Decision::Pass);
+        let repeat = KeyEvent::new(KEY_LEFTCTRL, REPEAT, Duration::from_millis(500));
+        assert_eq!(engine.process(repeat), Decision::Pass);
+        let release = KeyEvent::new(KEY_LEFTCTRL, RELEASE, Duration::from_millis(600));
+        assert_eq!(engine.process(release), Decision::Pass);
+        assert_eq!(engine.take_deferred(), []);
+    }
+
+    #[test]
+    fn single_key_chord_is_swallowed() {
+        let mut engine = DebounceEngine::new(EngineConfig {
+            toggle_hotkey: vec![KEY_PAUSE],
+            ..engine().config
+        });
+        let events = [(KEY_PAUSE, PRESS, 0), (KEY_PAUSE, RELEASE, 10)];
+        assert_eq!(emitted(&mut engine, &events), []);
+        assert!(engine.is_paused());
+    }
+
+    #[test]
+    fn incomplete_chord_flushes_keys_held_back() {
+        let mut engine = engine();
+        let events = [
+            (KEY_LEFTCTRL, PRESS, 0),
+            (KEY_LEFTCTRL, REPEAT, 5),
+            (KEY_C, PRESS, 10),
+            (KEY_C, RELEASE, 20),
+            (KEY_LEFTCTRL, RELEASE, 30),
+        ];
+        assert_eq!(emitted(&mut engine, &events), events);
+
+        let events = [(KEY_LEFTALT, PRESS, 100), (KEY_LEFTALT, RELEASE, 110)];
+        assert_eq!(emitted(&mut engine, &events), events);
+        assert!(!engine.is_paused());
+    }
+
+    #[test]
+    fn chord_keeps_release_of_keys_already_passed() {
+        let mut engine = engine();
+        let events = [
+            (KEY_A, PRESS, 0),
+            (KEY_LEFTCTRL, PRESS, 10),
+            (KEY_A, RELEASE, 20),
+            (KEY_LEFTALT, PRESS, 30),
+            (KEY_PAUSE, PRESS, 40),
+            (KEY_PAUSE, RELEASE, 50),
+            (KEY_LEFTALT, RELEASE, 60),
+            (KEY_LEFTCTRL, RELEASE, 70),
+        ];
+        assert_eq!(
+            emitted(&mut engine, &events),
+            [
+                (KEY_A, PRESS, 0),
+                (KEY_LEFTCTRL, PRESS, 10),
+                (KEY_A, RELEASE, 20),
+                (KEY_LEFTCTRL, RELEASE, 70),
+            ]
+        );
+        assert!(engine.is_paused());
+    }
+
+    #[test]
+    fn bypass_key_passes_chatter_while_held() {
+        let mut engine = engine();
+        let events = [
+            (KEY_RIGHTALT, PRESS, 0),
+            (KEY_A, PRESS, 10),
+            (KEY_A, RELEASE, 15),
+            (KEY_A, PRESS, 20),
+            (KEY_A, RELEASE, 25),
+            (KEY_RIGHTALT, RELEASE, 30),
+        ];
+        assert_eq!(emitted(&mut engine, &events), events);
+
+        let events = [(KEY_A, PRESS, 40), (KEY_A, RELEASE, 45)];
+        assert_eq!(emitted(&mut engine, &events), []);
+    }
+
+    #[test]
+    fn release_held_releases_emitted_keys_only() {
+        let mut engine = engine();
+        assert_eq!(
+            emitted(&mut engine, &[(KEY_A, PRESS, 0)]),
+            [(KEY_A, PRESS, 0)]
+        );
+        assert_eq!(
+            engine.release_held(Duration::from_millis(10)),
+            [KeyEvent::new(KEY_A, RELEASE, Duration::from_millis(10))]
+        );
+
+        let events = [(KEY_LEFTCTRL, PRESS, 20), (KEY_LEFTALT, PRESS, 25)];
+        assert_eq!(emitted(&mut engine, &events), [(KEY_LEFTCTRL, PRESS, 20)]);
+        assert_eq!(
+            engine.release_held(Duration::from_millis(30)),
+            [KeyEvent::new(
+                KEY_LEFTCTRL,
+                RELEASE,
+                Duration::from_millis(30)
+            )]
+        );
+        assert!(!engine.is_held(KEY_LEFTALT));
+    }
+}
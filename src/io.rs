@@ -0,0 +1,307 @@
+//! Where key events come from and go to.
+//!
+//! A frontend reads events from an [`InputSource`], filters them with a
+//! [`DebounceEngine`](crate::engine::DebounceEngine) and writes the ones that pass to an
+//! [`OutputSink`]. Besides the platform's own devices, events can come from a channel or be
+//! played back from text, one event per line:
+//!
+//! ```text
+//! # milliseconds  key code  value
+//! 0.000 30 1
+//! 10.000 30 0
+//! ```
+//!
+//! [`InputEventRecord`] reads and writes the binary records of evdev devices, as passed
+//! between interception-tools plugins.
+
+use crate::engine::KeyEvent;
+use std::io::{BufRead, ErrorKind, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+/// Event type of key events in a `struct input_event`.
+pub const EV_KEY: u16 = 0x01;
+
+/// A source of key events, such as a keyboard.
+pub trait InputSource<K = u16, T = Duration> {
+    /// Blocks until events are available, returning them in the order they happened. An
+    /// empty list means the input has ended. Sources may also fail with
+    /// [`std::io::ErrorKind::Interrupted`] while none come, so that their caller can check
+    /// whether to stop; reading again carries on.
+    fn read(&mut self) -> std::io::Result<Vec<KeyEvent<K, T>>>;
+
+    /// Blocks until the input is back after [`InputSource::read`] failed with `error`,
+    /// returning whether it is. Sources that cannot recover return `false` right away.
+    fn reconnect(&mut self, _error: &std::io::Error) -> bool {
+        false
+    }
+}
+
+/// A destination of key events, such as a virtual keyboard.
+pub trait OutputSink<K = u16, T = Duration> {
+    fn emit(&mut self, event: KeyEvent<K, T>) -> std::io::Result<()>;
+}
+
+/// Reads the events sent to the channel, until every sender is dropped.
+impl<K, T> InputSource<K, T> for Receiver<KeyEvent<K, T>> {
+    fn read(&mut self) -> std::io::Result<Vec<KeyEvent<K, T>>> {
+        let Ok(first) = self.recv() else {
+            return Ok(Vec::new());
+        };
+        Ok([first].into_iter().chain(self.try_iter()).collect())
+    }
+}
+
+/// Sends events to the channel, failing once the receiver is dropped.
+impl<K, T> OutputSink<K, T> for Sender<KeyEvent<K, T>> {
+    fn emit(&mut self, event: KeyEvent<K, T>) -> std::io::Result<()> {
+        self.send(event)
+            .map_err(|_| std::io::Error::from(ErrorKind::BrokenPipe))
+    }
+}
+
+/// Collects events, mostly for tests.
+impl<K, T> OutputSink<K, T> for Vec<KeyEvent<K, T>> {
+    fn emit(&mut self, event: KeyEvent<K, T>) -> std::io::Result<()> {
+        self.push(event);
+        Ok(())
+    }
+}
+
+/// Plays back events written as text, from a file or stdin. Blank lines and lines
+/// starting with `#` are skipped.
+pub struct TextSource<R> {
+    reader: R,
+    line_number: usize,
+}
+
+impl<R: BufRead> TextSource<R> {
+    pub fn new(reader: R) -> TextSource<R> {
+        TextSource {
+            reader,
+            line_number: 0,
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for TextSource<R> {
+    fn read(&mut self) -> std::io::Result<Vec<KeyEvent>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(Vec::new());
+            }
+            self.line_number += 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            return parse_event(line).map(|event| vec![event]).map_err(|e| {
+                let message = format!("line {}: {e}: {line:?}", self.line_number);
+                std::io::Error::new(ErrorKind::InvalidData, message)
+            });
+        }
+    }
+}
+
+fn parse_event(line: &str) -> Result<KeyEvent, String> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [timestamp, code, value] = fields[..] else {
+        return Err("expected a time in milliseconds, a key code and a value".to_owned());
+    };
+    let milliseconds = timestamp
+        .parse::<f64>()
+        .ok()
+        .filter(|ms| ms.is_finite() && *ms >= 0.0)
+        .ok_or("invalid time")?;
+    let code = match code.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => code.parse::<u16>(),
+    }
+    .map_err(|_| "invalid key code")?;
+    let value = value.parse::<i32>().map_err(|_| "invalid value")?;
+    Ok(KeyEvent::new(
+        code,
+        value,
+        Duration::from_secs_f64(milliseconds / 1000.0),
+    ))
+}
+
+/// Writes events as text, in the format read by [`TextSource`].
+pub struct TextSink<W> {
+    writer: W,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W) -> TextSink<W> {
+        TextSink { writer }
+    }
+}
+
+impl<W: Write> OutputSink for TextSink<W> {
+    fn emit(&mut self, event: KeyEvent) -> std::io::Result<()> {
+        let milliseconds = event.timestamp.as_secs_f64() * 1000.0;
+        writeln!(
+            self.writer,
+            "{milliseconds:.3} {} {}",
+            event.code, event.value
+        )
+    }
+}
+
+/// A Linux `struct input_event`, in the binary layout read from evdev devices and passed
+/// between interception-tools plugins. The timestamp fields are as wide as a C `long`, so a
+/// record takes 24 bytes on 64-bit platforms and 16 on 32-bit ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEventRecord {
+    pub seconds: i64,
+    pub microseconds: i64,
+    /// Event type, such as [`EV_KEY`].
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+const LONG_SIZE: usize = size_of::<isize>();
+
+impl InputEventRecord {
+    pub const SIZE: usize = 2 * LONG_SIZE + 8;
+
+    pub fn from_bytes(bytes: &[u8; InputEventRecord::SIZE]) -> InputEventRecord {
+        let long = |offset: usize| {
+            let field = bytes[offset..offset + LONG_SIZE].try_into().unwrap();
+            isize::from_ne_bytes(field) as i64
+        };
+        let rest = 2 * LONG_SIZE;
+        InputEventRecord {
+            seconds: long(0),
+            microseconds: long(LONG_SIZE),
+            kind: u16::from_ne_bytes([bytes[rest], bytes[rest + 1]]),
+            code: u16::from_ne_bytes([bytes[rest + 2], bytes[rest + 3]]),
+            value: i32::from_ne_bytes(bytes[rest + 4..].try_into().unwrap()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; InputEventRecord::SIZE] {
+        let mut bytes = [0; InputEventRecord::SIZE];
+        let rest = 2 * LONG_SIZE;
+        bytes[..LONG_SIZE].copy_from_slice(&(self.seconds as isize).to_ne_bytes());
+        bytes[LONG_SIZE..rest].copy_from_slice(&(self.microseconds as isize).to_ne_bytes());
+        bytes[rest..rest + 2].copy_from_slice(&self.kind.to_ne_bytes());
+        bytes[rest + 2..rest + 4].copy_from_slice(&self.code.to_ne_bytes());
+        bytes[rest + 4..].copy_from_slice(&self.value.to_ne_bytes());
+        bytes
+    }
+
+    /// Returns the record of a key event.
+    pub fn from_key_event(event: &KeyEvent) -> InputEventRecord {
+        InputEventRecord {
+            seconds: event.timestamp.as_secs() as i64,
+            microseconds: event.timestamp.subsec_micros().into(),
+            kind: EV_KEY,
+            code: event.code,
+            value: event.value,
+        }
+    }
+
+    /// Returns the key event this record holds, if it is one.
+    pub fn key_event(&self) -> Option<KeyEvent> {
+        let timestamp = Duration::from_secs(self.seconds.max(0) as u64)
+            + Duration::from_micros(self.microseconds.max(0) as u64);
+        (self.kind == EV_KEY).then(|| KeyEvent::new(self.code, self.value, timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{PRESS, RELEASE};
+    use std::sync::mpsc::channel;
+
+    fn at(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn channel_carries_events_until_senders_are_gone() {
+        let (mut sender, mut receiver) = channel();
+        sender.emit(KeyEvent::new(30, PRESS, at(0))).unwrap();
+        sender.emit(KeyEvent::new(30, RELEASE, at(10))).unwrap();
+        assert_eq!(
+            receiver.read().unwrap(),
+            [
+                KeyEvent::new(30, PRESS, at(0)),
+                KeyEvent::new(30, RELEASE, at(10))
+            ]
+        );
+        drop(sender);
+        assert_eq!(receiver.read().unwrap(), []);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = "# chatter\n0 30 1\n\n10.5 0x1e 0\n   \n20 30 2\n";
+        let mut source = TextSource::new(text.as_bytes());
+        let mut sink = TextSink::new(Vec::new());
+        loop {
+            let events = source.read().unwrap();
+            if events.is_empty() {
+                break;
+            }
+            for event in events {
+                sink.emit(event).unwrap();
+            }
+        }
+        assert_eq!(
+            String::from_utf8(sink.writer).unwrap(),
+            "0.000 30 1\n10.500 30 0\n20.000 30 2\n"
+        );
+    }
+
+    #[test]
+    fn input_event_records_round_trip() {
+        let record = InputEventRecord {
+            seconds: 1_700_000_000,
+            microseconds: 123_456,
+            kind: EV_KEY,
+            code: 30,
+            value: PRESS,
+        };
+        let bytes = record.to_bytes();
+        assert_eq!(bytes.len(), InputEventRecord::SIZE);
+        assert_eq!(InputEventRecord::from_bytes(&bytes), record);
+
+        let event = record.key_event().unwrap();
+        assert_eq!(event.timestamp, Duration::new(1_700_000_000, 123_456_000));
+        assert_eq!(InputEventRecord::from_key_event(&event), record);
+
+        let sync = InputEventRecord {
+            kind: 0,
+            code: 0,
+            value: 0,
+            ..record
+        };
+        assert_eq!(InputEventRecord::from_bytes(&sync.to_bytes()), sync);
+        assert_eq!(sync.key_event(), None);
+    }
+
+    #[test]
+    fn text_source_reports_the_line_of_invalid_events() {
+        let cases = [
+            (
+                "0 30",
+                "line 2: expected a time in milliseconds, a key code and a value: \"0 30\"",
+            ),
+            ("-5 30 1", "line 2: invalid time: \"-5 30 1\""),
+            ("0 KEY_A 1", "line 2: invalid key code: \"0 KEY_A 1\""),
+            ("0 30 down", "line 2: invalid value: \"0 30 down\""),
+        ];
+        for (line, message) in cases {
+            let text = format!("# header\n{line}\n0 30 1\n");
+            let error = TextSource::new(text.as_bytes()).read().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), message);
+        }
+    }
+}
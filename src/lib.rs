@@ -0,0 +1,44 @@
+//! Key debouncing, independent of any platform or input library.
+//!
+//! [`DebounceEngine`](engine::DebounceEngine) decides, for every key event read from a
+//! keyboard, whether to pass it on or to suppress it as chatter. Frontends read events from
+//! the platform, feed them to the engine and emit the ones it lets through, after those it
+//! held back for the toggle chord and hands over in
+//! [`take_deferred`](engine::DebounceEngine::take_deferred):
+//!
+//! ```
+//! use debounce_keyboard::engine::{DebounceEngine, Decision, EngineConfig, KeyEvent, PRESS, RELEASE};
+//! use std::time::Duration;
+//!
+//! const KEY_A: u16 = 30;
+//! let ms = Duration::from_millis;
+//! let mut engine = DebounceEngine::new(EngineConfig {
+//!     delay: ms(50),
+//!     keys: [KEY_A].into(),
+//!     ..EngineConfig::default()
+//! });
+//!
+//! assert_eq!(engine.process(KeyEvent::new(KEY_A, PRESS, ms(0))), Decision::Pass);
+//! assert_eq!(engine.process(KeyEvent::new(KEY_A, RELEASE, ms(10))), Decision::Pass);
+//! // The switch bounced: a second press 20ms after the first one is dropped.
+//! assert_eq!(engine.process(KeyEvent::new(KEY_A, PRESS, ms(20))), Decision::Suppress);
+//! assert_eq!(engine.process(KeyEvent::new(KEY_A, RELEASE, ms(25))), Decision::Suppress);
+//! ```
+//!
+//! [`config`] reads the settings of the engine from config files, naming keys through the
+//! platform's [`Keymap`](config::Keymap), and [`calibration`] measures how keys bounce to
+//! recommend those settings. With [`adaptive`], the engine learns the delay of every key
+//! from how it bounces instead, [`corrections`] tells from the user's corrections where the
+//! delays got it wrong, [`bouncing`] finds the keys that need debouncing at all, and
+//! [`chatter`] lengthens the delay of keys while they chatter in bursts. The daemon keeps
+//! what it learned in the [`state`] file.
+
+pub mod adaptive;
+pub mod bouncing;
+pub mod calibration;
+pub mod chatter;
+pub mod config;
+pub mod corrections;
+pub mod engine;
+pub mod io;
+pub mod state;
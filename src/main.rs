@@ -1,14 +1,40 @@
 extern crate core;
 
+#[cfg(target_os = "linux")]
+use crate::device::calibrate::calibrate;
 use crate::device::command_line::Cli;
 #[cfg(target_os = "linux")]
 use crate::device::command_line::Commands;
-use crate::device::config::{ConfigHolder, load_config, save_config_to_path};
+use crate::device::config::{ConfigHolder, load_config};
+#[cfg(target_os = "linux")]
+use crate::device::config::{ConfigSources, check_config, get_keys_code, save_config_to_path};
+#[cfg(target_os = "linux")]
+use crate::device::control::{
+    DaemonState, listen, run, run_plugin, save_periodically, save_state, send_command,
+};
 #[cfg(target_os = "linux")]
 use crate::device::debounce::{
-    KeyEventHolder, combine_u16_to_u32, create_virtual_device, emit_key_event, list_devices,
-    receive_event, should_skip, split_u32_to_u16,
+    combine_u16_to_u32, create_virtual_device, find_device, get_all_keys_code, list_devices,
+    read_in_background, split_u32_to_u16,
 };
+#[cfg(target_os = "linux")]
+use crate::device::tester::test_keys;
+#[cfg(target_os = "linux")]
+use debounce_keyboard::io::{TextSink, TextSource};
+#[cfg(target_os = "linux")]
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
+#[cfg(target_os = "linux")]
+use std::fs::File;
+#[cfg(target_os = "linux")]
+use std::io::{BufRead, BufReader, IsTerminal};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsFd;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::AtomicBool;
+#[cfg(target_os = "linux")]
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
 
 mod device;
 use device::*;
@@ -18,26 +44,39 @@ fn main() {
     let args = Cli::parse();
 
     let config_path = args.config_path.unwrap_or_else(|| "config.ini".into());
-    let mut config: ConfigHolder = load_config(&config_path);
-    save_config_to_path(&config_path, &config);
+    let config: ConfigHolder = load_config(&config_path);
 
-    let mut key_event_holder = KeyEventHolder::new(config.delay_ms);
-    
-    setup_windows_ll_keyboard_hook(key_event_holder, config);
+    if let Err(e) = setup_windows_ll_keyboard_hook(config.engine_config()) {
+        log::error!("Failed to install the keyboard hook: {e}");
+        exit(1);
+    }
     run_message_loop()
 }
 
 #[cfg(target_os = "linux")]
 fn main() {
     let args = Cli::parse();
+    logging::init(
+        args.verbose as i8 - args.quiet as i8,
+        args.log_format,
+        args.redact,
+    );
 
-    let config_path = args.config_path.unwrap_or_else(|| "config.ini".into());
+    let mut config_sources = ConfigSources::locate(args.config_path);
+    if let Some(delay_ms) = args.delay_ms {
+        config_sources.set_override("delay_ms", delay_ms.to_string());
+    }
+    if let Some(keys) = args.keys {
+        config_sources.set_override("keys", keys);
+    }
+    if let Some(profile) = args.profile {
+        config_sources.set_override("profile", profile);
+    }
 
-    let devices = list_devices();
     let device_number = match args.command {
         Some(command) => match command {
             Commands::ListDevices => {
-                for (i, device) in devices.iter().enumerate() {
+                for (i, device) in list_devices().iter().enumerate() {
                     println!(
                         "{:?}: {}",
                         i,
@@ -50,15 +89,161 @@ fn main() {
                 .trim()
                 .parse::<u32>()
                 .unwrap_or_else(|_| panic!("Invalid argument: should be a number")),
+            Commands::CheckConfig => {
+                let problems = check_config(&config_sources);
+                if problems.is_empty() {
+                    println!("Configuration OK");
+                    exit(0);
+                }
+                for problem in problems {
+                    eprintln!("{problem}");
+                }
+                exit(1);
+            }
+            Commands::Ctl { socket, command } => {
+                let socket = socket.unwrap_or_else(|| load_config(&config_sources).control_socket);
+                exit(if send_command(&socket, &command.join(" ")) {
+                    0
+                } else {
+                    1
+                });
+            }
+            Commands::Plugin => {
+                let config = load_config(&config_sources);
+                log::info!(
+                    "Filtering key events from stdin using profile {}",
+                    config.profile
+                );
+                let mut state = DaemonState::new(config_sources, config);
+                state.load_state();
+                let state = Arc::new(Mutex::new(state));
+                save_periodically(Arc::clone(&state));
+                let (next_profile, reload) = register_signals();
+                let stop = register_stop();
+                // Unbuffered, as records are waited for on the file descriptor.
+                let input = std::io::stdin()
+                    .as_fd()
+                    .try_clone_to_owned()
+                    .map(File::from)
+                    .unwrap_or_else(|e| {
+                        log::error!("Failed to read stdin: {e}");
+                        exit(1)
+                    });
+                let result = run_plugin(
+                    input,
+                    std::io::stdout().lock(),
+                    &state,
+                    &next_profile,
+                    &reload,
+                    &stop,
+                );
+                save_state(&state);
+                if let Err(e) = result {
+                    log::error!("Failed to forward key events: {e}");
+                    exit(1);
+                }
+                exit(0);
+            }
+            Commands::Replay { file } => {
+                let reader: Box<dyn BufRead> = if file.as_os_str() == "-" {
+                    Box::new(std::io::stdin().lock())
+                } else {
+                    match File::open(&file) {
+                        Ok(file) => Box::new(BufReader::new(file)),
+                        Err(e) => {
+                            log::error!("Failed to open {}: {e}", file.display());
+                            exit(1);
+                        }
+                    }
+                };
+                let config = load_config(&config_sources);
+                let state = Arc::new(Mutex::new(DaemonState::new(config_sources, config)));
+                let no_signal = AtomicBool::new(false);
+                let mut sink = TextSink::new(std::io::stdout().lock());
+                let result = run(
+                    &mut TextSource::new(reader),
+                    &mut sink,
+                    &state,
+                    &no_signal,
+                    &no_signal,
+                    &no_signal,
+                );
+                if let Err(e) = result {
+                    log::error!("Failed to replay {}: {e}", file.display());
+                    exit(1);
+                }
+                exit(0);
+            }
+            Commands::Test { device, threshold } => {
+                let config = load_config(&config_sources);
+                let mut engine_config = config.engine_config();
+                if let Some(threshold) = threshold {
+                    engine_config.delay = Duration::from_millis(threshold);
+                    engine_config.key_delays.clear();
+                }
+                let device = choose_device(device, &config);
+                println!(
+                    "Testing {}, press Ctrl+C to stop",
+                    device.device_internal.name().unwrap_or("Unknown device")
+                );
+                let stop = register_stop();
+                let events = read_in_background(device);
+                let result = test_keys(
+                    &events,
+                    &engine_config,
+                    std::io::stdout().lock(),
+                    std::io::stdout().is_terminal(),
+                    &stop,
+                );
+                if let Err(e) = result {
+                    log::error!("Failed to test keys: {e}");
+                    exit(1);
+                }
+                exit(0);
+            }
+            Commands::Calibrate { device, keys } => {
+                let config = load_config(&config_sources);
+                let keys = match keys {
+                    Some(keys) => {
+                        let keys = keys.split(',').map(str::trim).collect::<Vec<_>>();
+                        get_keys_code(&keys).unwrap_or_else(|e| {
+                            log::error!("{e}");
+                            exit(1)
+                        })
+                    }
+                    None if config.keys.len() < get_all_keys_code().len() => config.keys.clone(),
+                    None => get_keys_code(&["@letters"]).unwrap(),
+                };
+                let device = choose_device(device, &config);
+                println!(
+                    "Calibrating {}",
+                    device.device_internal.name().unwrap_or("Unknown device")
+                );
+                let events = read_in_background(device);
+                let result = calibrate(
+                    &events,
+                    &keys,
+                    std::io::stdin().lock(),
+                    std::io::stdout().lock(),
+                    config,
+                    &config_sources.user,
+                );
+                if let Err(e) = result {
+                    log::error!("Failed to calibrate: {e}");
+                    exit(1);
+                }
+                exit(0);
+            }
         },
         None => 0,
     };
 
-    let mut config: ConfigHolder = load_config(&config_path);
+    let mut config: ConfigHolder = load_config(&config_sources);
 
+    let devices = list_devices();
     let mut device: debounce::Device;
     if device_number == 0 && config.device_id == 0 {
-        eprintln!(
+        log::error!(
             "No device provided: use the select option or provide 'device_id' in config file."
         );
         exit(1);
@@ -84,26 +269,121 @@ fn main() {
             .name()
             .expect("Unknow device name!")
             .to_owned();
-        save_config_to_path(&config_path.clone(), &config);
+        save_config_to_path(&config_sources.user, &config);
     }
 
-    let mut key_event_holder = KeyEventHolder::new(config.delay_ms);
-    let mut virtual_device = create_virtual_device();
+    let mut virtual_device = create_virtual_device(&device);
+
+    let (next_profile, reload) = register_signals();
+    let stop = register_stop();
 
-    device.grab();
-    println!(
-        "Watching {} for key events",
-        device.device_internal.name().unwrap_or("Unknown device")
+    if let Err(e) = device.grab() {
+        log::error!("Failed to grab the device: {e}");
+        exit(1);
+    }
+    log::info!(
+        "Watching {} for key events using profile {}",
+        device.device_internal.name().unwrap_or("Unknown device"),
+        config.profile
     );
-    loop {
-        let fetched_events = receive_event(&mut device);
-        for event in fetched_events {
-            if !should_skip(&event, &mut key_event_holder, &config) {
-                #[cfg(debug_assertions)]
-                println!("{:?}", event);
-                emit_key_event(event, &mut virtual_device)
-            }
+
+    let control_socket = config.control_socket.clone();
+    let control_group = config.control_group.clone();
+    let dbus_bus = config.dbus_bus.clone();
+    #[cfg(not(feature = "dbus"))]
+    if dbus_bus.is_some() {
+        log::warn!("D-Bus disabled: built without the 'dbus' feature");
+    }
+    let metrics_address = config.metrics_address;
+    #[cfg(not(feature = "metrics"))]
+    if metrics_address.is_some() {
+        log::warn!("Metrics disabled: built without the 'metrics' feature");
+    }
+    let mut state = DaemonState::new(config_sources, config);
+    state.load_state();
+    let state = Arc::new(Mutex::new(state));
+    save_periodically(Arc::clone(&state));
+    match listen(
+        &control_socket,
+        control_group.as_deref(),
+        Arc::clone(&state),
+    ) {
+        Ok(()) => log::info!("Listening for commands on {}", control_socket.display()),
+        Err(e) => log::warn!("Control socket disabled: {e}"),
+    }
+    #[cfg(feature = "dbus")]
+    let _dbus_connection = dbus_bus.and_then(|bus| {
+        dbus::serve(&bus, Arc::clone(&state))
+            .inspect(|_| log::info!("Registered {} on the {bus} bus", dbus::BUS_NAME))
+            .inspect_err(|e| log::warn!("D-Bus disabled: {e}"))
+            .ok()
+    });
+    #[cfg(feature = "metrics")]
+    if let Some(address) = metrics_address {
+        match metrics::serve(address, Arc::clone(&state)) {
+            Ok(()) => log::info!("Serving metrics on http://{address}/metrics"),
+            Err(e) => log::warn!("Metrics disabled: {e}"),
         }
     }
+
+    let result = run(
+        &mut device,
+        &mut virtual_device,
+        &state,
+        &next_profile,
+        &reload,
+        &stop,
+    );
+    save_state(&state);
+    if let Err(e) = result {
+        log::error!("Failed to forward key events: {e}");
+        exit(1);
+    }
 }
 
+/// Returns the device numbered as by the list command, or the configured one.
+#[cfg(target_os = "linux")]
+fn choose_device(number: Option<usize>, config: &ConfigHolder) -> debounce::Device {
+    let device = match number {
+        Some(number) => list_devices().into_iter().nth(number),
+        None if config.device_id == 0 => {
+            log::error!("No device provided: use --device or provide 'device_id' in config file.");
+            exit(1);
+        }
+        None => {
+            let (vendor, product) = split_u32_to_u16(config.device_id);
+            find_device(vendor, product, &config.device_name)
+        }
+    };
+    device.unwrap_or_else(|| {
+        log::error!("Device not found: check the list command");
+        exit(1)
+    })
+}
+
+/// Returns flags raised by SIGUSR1, to switch to the next profile, and by SIGHUP, to reload
+/// the configured one.
+#[cfg(target_os = "linux")]
+fn register_signals() -> (Arc<AtomicBool>, Arc<AtomicBool>) {
+    let next_profile = Arc::new(AtomicBool::new(false));
+    let reload = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGUSR1, Arc::clone(&next_profile))
+        .expect("Failed to register signal handler");
+    signal_hook::flag::register(SIGHUP, Arc::clone(&reload))
+        .expect("Failed to register signal handler");
+    (next_profile, reload)
+}
+
+/// Returns a flag raised by SIGINT or SIGTERM, for the daemon to stop once done with the
+/// events at hand. A second signal terminates it right away.
+#[cfg(target_os = "linux")]
+fn register_stop() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&stop))
+            .expect("Failed to register signal handler");
+        signal_hook::flag::register(signal, Arc::clone(&stop))
+            .expect("Failed to register signal handler");
+    }
+    stop
+}
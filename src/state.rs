@@ -0,0 +1,193 @@
+//! What the daemon learns about a keyboard, kept across restarts in the state file, see
+//! [`ConfigHolder::state_file`](crate::config::ConfigHolder::state_file).
+//!
+//! The file is JSON naming keys as the platform's [`Keymap`] does. It belongs to the
+//! daemon: entries it does not know, such as keys of another platform, are dropped when it
+//! is written again.
+
+use crate::config::{Keymap, write_atomically};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+/// The on-disk layout of the state file.
+#[derive(Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default)]
+    learned_delay_ms: BTreeMap<String, u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    delay_correction_ms: BTreeMap<String, i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    bouncing_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    observed_presses: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// What the daemon learned, with keys identified by the platform's [`Keymap`].
+pub struct LearnedState<M: Keymap> {
+    /// Delays learned for keys, see [`adaptive`](crate::adaptive).
+    pub delays: BTreeMap<M::Key, Duration>,
+    /// Milliseconds corrections added to the delays of keys, negative when they removed
+    /// some, see [`corrections`](crate::corrections).
+    pub delay_corrections: BTreeMap<M::Key, i64>,
+    /// Keys found bouncing, see [`bouncing`](crate::bouncing).
+    pub bouncing_keys: BTreeSet<M::Key>,
+    /// Presses seen while looking for the keys that bounce.
+    pub observed_presses: u64,
+}
+
+impl<M: Keymap> Default for LearnedState<M> {
+    fn default() -> LearnedState<M> {
+        LearnedState {
+            delays: BTreeMap::new(),
+            delay_corrections: BTreeMap::new(),
+            bouncing_keys: BTreeSet::new(),
+            observed_presses: 0,
+        }
+    }
+}
+
+impl<M: Keymap> Clone for LearnedState<M> {
+    fn clone(&self) -> LearnedState<M> {
+        LearnedState {
+            delays: self.delays.clone(),
+            delay_corrections: self.delay_corrections.clone(),
+            bouncing_keys: self.bouncing_keys.clone(),
+            observed_presses: self.observed_presses,
+        }
+    }
+}
+
+impl<M: Keymap> PartialEq for LearnedState<M> {
+    fn eq(&self, other: &LearnedState<M>) -> bool {
+        self.delays == other.delays
+            && self.delay_corrections == other.delay_corrections
+            && self.bouncing_keys == other.bouncing_keys
+            && self.observed_presses == other.observed_presses
+    }
+}
+
+impl<M: Keymap> LearnedState<M> {
+    /// Reads the state file, returning an empty state when it does not exist yet.
+    pub fn load(path: &Path) -> std::io::Result<LearnedState<M>> {
+        let contents = match std::fs::read(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(LearnedState::default()),
+            result => result?,
+        };
+        let file: StateFile = serde_json::from_slice(&contents)?;
+        let delays = file
+            .learned_delay_ms
+            .into_iter()
+            .filter_map(|(name, delay_ms)| {
+                let key = M::key(&name)?;
+                Some((key, Duration::from_millis(delay_ms)))
+            })
+            .collect();
+        let delay_corrections = file
+            .delay_correction_ms
+            .into_iter()
+            .filter_map(|(name, ms)| Some((M::key(&name)?, ms)))
+            .collect();
+        let bouncing_keys = file
+            .bouncing_keys
+            .iter()
+            .filter_map(|name| M::key(name))
+            .collect();
+        Ok(LearnedState {
+            delays,
+            delay_corrections,
+            bouncing_keys,
+            observed_presses: file.observed_presses,
+        })
+    }
+
+    /// Writes the state file, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let file = StateFile {
+            learned_delay_ms: self
+                .delays
+                .iter()
+                .map(|(&key, delay)| (M::name(key), delay.as_millis() as u64))
+                .collect(),
+            delay_correction_ms: self
+                .delay_corrections
+                .iter()
+                .map(|(&key, &ms)| (M::name(key), ms))
+                .collect(),
+            bouncing_keys: self.bouncing_keys.iter().map(|&key| M::name(key)).collect(),
+            observed_presses: self.observed_presses,
+        };
+        if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut contents = serde_json::to_vec_pretty(&file)?;
+        contents.push(b'\n');
+        write_atomically(path, &contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names keys `K<code>`.
+    struct TestKeys;
+
+    impl Keymap for TestKeys {
+        type Key = u16;
+
+        fn key(name: &str) -> Option<u16> {
+            name.strip_prefix('K')?.parse().ok()
+        }
+
+        fn name(key: u16) -> String {
+            format!("K{key}")
+        }
+
+        fn all_keys() -> Vec<u16> {
+            (0..10).collect()
+        }
+    }
+
+    #[test]
+    fn learned_state_round_trips() {
+        let dir =
+            std::env::temp_dir().join(format!("debounce-keyboard-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("nested/state.json");
+        assert!(LearnedState::<TestKeys>::load(&path).unwrap() == LearnedState::default());
+
+        let state = LearnedState::<TestKeys> {
+            delays: [
+                (3, Duration::from_millis(27)),
+                (7, Duration::from_millis(90)),
+            ]
+            .into(),
+            delay_corrections: [(3, -10)].into(),
+            bouncing_keys: [7].into(),
+            observed_presses: 1500,
+        };
+        state.save(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\n  \"learned_delay_ms\": {\n    \"K3\": 27,\n    \"K7\": 90\n  },\n  \
+             \"delay_correction_ms\": {\n    \"K3\": -10\n  },\n  \"bouncing_keys\": [\n    \"K7\"\n  ],\n  \
+             \"observed_presses\": 1500\n}\n"
+        );
+        assert!(LearnedState::<TestKeys>::load(&path).unwrap() == state);
+
+        std::fs::write(&path, "{\"learned_delay_ms\": {\"KEY_A\": 5, \"K1\": 12}}").unwrap();
+        let state = LearnedState::<TestKeys>::load(&path).unwrap();
+        assert_eq!(state.delays, [(1, Duration::from_millis(12))].into());
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(LearnedState::<TestKeys>::load(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
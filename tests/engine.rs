@@ -0,0 +1,135 @@
+//! Invariants of the debounce state machine over random key timelines.
+
+use debounce_keyboard::engine::{
+    DebounceEngine, Decision, EngineConfig, KeyEvent, PRESS, RELEASE, REPEAT,
+};
+use proptest::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+const KEY_A: u16 = 30;
+const KEY_S: u16 = 31;
+const KEY_D: u16 = 32;
+const KEY_F: u16 = 33;
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTALT: u16 = 56;
+const KEY_RIGHTALT: u16 = 100;
+
+const DELAY: Duration = Duration::from_millis(50);
+
+/// Turns `(key, milliseconds since the last event, whether to repeat)` steps into events a
+/// keyboard could send: a key is pressed when up, and repeated or released when down. Every
+/// key left down is released at the end.
+fn timeline(keys: &[u16], steps: &[(usize, u64, bool)]) -> Vec<KeyEvent> {
+    let mut down = HashSet::new();
+    let mut time = Duration::ZERO;
+    let mut events = Vec::new();
+    for &(key, gap, repeat) in steps {
+        let code = keys[key % keys.len()];
+        time += Duration::from_millis(gap);
+        let value = match (down.contains(&code), repeat) {
+            (false, _) => PRESS,
+            (true, true) => REPEAT,
+            (true, false) => RELEASE,
+        };
+        match value {
+            PRESS => down.insert(code),
+            RELEASE => down.remove(&code),
+            _ => false,
+        };
+        events.push(KeyEvent::new(code, value, time));
+    }
+    let mut left = down.into_iter().collect::<Vec<_>>();
+    left.sort_unstable();
+    for code in left {
+        time += Duration::from_millis(1);
+        events.push(KeyEvent::new(code, RELEASE, time));
+    }
+    events
+}
+
+/// Runs events through an engine and returns the ones it lets through, in order.
+fn filter(config: EngineConfig, events: &[KeyEvent]) -> Vec<KeyEvent> {
+    let mut engine = DebounceEngine::new(config);
+    let mut emitted = Vec::new();
+    for &event in events {
+        let decision = engine.process(event);
+        let deferred = engine
+            .take_deferred()
+            .into_iter()
+            .chain([(event, decision)]);
+        emitted.extend(
+            deferred
+                .filter(|&(_, decision)| decision == Decision::Pass)
+                .map(|(event, _)| event),
+        );
+    }
+    emitted
+}
+
+/// Checks that every key is pressed before being repeated or released, is not pressed
+/// twice in a row, and ends up released.
+fn check_balanced(emitted: &[KeyEvent]) -> Result<(), TestCaseError> {
+    let mut down = HashSet::new();
+    for event in emitted {
+        match event.value {
+            PRESS => prop_assert!(down.insert(event.code), "{event:?} while down"),
+            RELEASE => prop_assert!(down.remove(&event.code), "{event:?} while up"),
+            _ => prop_assert!(down.contains(&event.code), "{event:?} while up"),
+        }
+    }
+    prop_assert!(down.is_empty(), "{down:?} left down");
+    Ok(())
+}
+
+fn steps() -> impl Strategy<Value = Vec<(usize, u64, bool)>> {
+    prop::collection::vec((0..8usize, 0..120u64, any::<bool>()), 0..200)
+}
+
+proptest! {
+    #[test]
+    fn debounced_keys_are_balanced_and_spaced(steps in steps()) {
+        let events = timeline(&[KEY_A, KEY_S, KEY_D, KEY_F], &steps);
+        let config = EngineConfig {
+            delay: DELAY,
+            keys: [KEY_A, KEY_S].into(),
+            ..EngineConfig::default()
+        };
+        let emitted = filter(config, &events);
+        check_balanced(&emitted)?;
+
+        let mut last_press = HashMap::new();
+        let presses = emitted
+            .iter()
+            .filter(|event| event.value == PRESS && (event.code == KEY_A || event.code == KEY_S));
+        for event in presses {
+            if let Some(last) = last_press.insert(event.code, event.timestamp) {
+                prop_assert!(
+                    event.timestamp - last > DELAY,
+                    "{event:?} passed {:?} after the last press",
+                    event.timestamp - last
+                );
+            }
+        }
+
+        let unlisted = |event: &&KeyEvent| event.code == KEY_D || event.code == KEY_F;
+        prop_assert_eq!(
+            emitted.iter().filter(unlisted).collect::<Vec<_>>(),
+            events.iter().filter(unlisted).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn chord_and_bypass_keep_keys_balanced(steps in steps()) {
+        let keys = [KEY_A, KEY_S, KEY_LEFTCTRL, KEY_LEFTALT, KEY_RIGHTALT];
+        let events = timeline(&keys, &steps);
+        let config = EngineConfig {
+            delay: DELAY,
+            keys: [KEY_A, KEY_S, KEY_LEFTCTRL].into(),
+            toggle_hotkey: vec![KEY_LEFTCTRL, KEY_LEFTALT, KEY_S],
+            bypass_key: Some(KEY_RIGHTALT),
+            ..EngineConfig::default()
+        };
+        check_balanced(&filter(config, &events))?;
+    }
+}
@@ -0,0 +1,142 @@
+//! Runs the daemon as an interception-tools plugin, writing `struct input_event` records to
+//! its stdin and reading what it lets through from its stdout.
+
+use debounce_keyboard::engine::{KeyEvent, PRESS, RELEASE};
+use debounce_keyboard::io::InputEventRecord;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const KEY_A: u16 = 30;
+const KEY_BACKSPACE: u16 = 14;
+/// Sent after the script, and never debounced, to tell when the plugin is done with it.
+const END: u16 = 194;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns an empty directory of its own for a test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "debounce-keyboard-plugin-{name}-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Starts the plugin with `settings` as its config file and `args` before the subcommand.
+fn start(dir: &Path, settings: &str, args: &[&str]) -> Child {
+    let config = dir.join("config.ini");
+    std::fs::write(&config, settings).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_debounce-keyboard"))
+        .arg("--config-path")
+        .arg(&config)
+        .args(args)
+        .arg("plugin")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+fn send(input: &mut ChildStdin, events: &[KeyEvent]) {
+    for event in events {
+        input
+            .write_all(&InputEventRecord::from_key_event(event).to_bytes())
+            .unwrap();
+    }
+    input.flush().unwrap();
+}
+
+fn at(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn sigterm_saves_learned_delays() {
+    let dir = test_dir("sigterm");
+    let state_file = dir.join("state.json");
+    let settings = format!(
+        "keys=KEY_A\nadaptive_delay=true\nstate_file={}\n",
+        state_file.display()
+    );
+    let mut plugin = start(&dir, &settings, &["-q"]);
+
+    // KEY_A bounces 5ms after every press.
+    let mut input = plugin.stdin.take().unwrap();
+    let mut events = Vec::new();
+    for i in 0..=10 {
+        for (value, ms) in [(PRESS, 0), (RELEASE, 3), (PRESS, 5), (RELEASE, 8)] {
+            events.push(KeyEvent::new(KEY_A, value, at(i * 150 + ms)));
+        }
+    }
+    events.push(KeyEvent::new(END, PRESS, at(2000)));
+    send(&mut input, &events);
+
+    let mut output = plugin.stdout.take().unwrap();
+    let mut bytes = [0; InputEventRecord::SIZE];
+    loop {
+        output.read_exact(&mut bytes).unwrap();
+        let record = InputEventRecord::from_bytes(&bytes);
+        if record.key_event().is_some_and(|event| event.code == END) {
+            break;
+        }
+    }
+
+    // SAFETY: kill has no memory safety requirements.
+    assert_eq!(unsafe { libc::kill(plugin.id() as i32, libc::SIGTERM) }, 0);
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = plugin.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            plugin.kill().unwrap();
+            panic!("The plugin did not stop on SIGTERM");
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    // Stdin is still open: the plugin stopped on the signal, not at the end of its input.
+    drop(input);
+    assert!(status.success(), "{status}");
+    let saved = std::fs::read_to_string(&state_file).unwrap();
+    assert!(saved.contains("\"KEY_A\": 27"), "{saved}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn corrections_of_keys_never_suppressed_are_pseudonymous() {
+    let dir = test_dir("pseudonym");
+    let settings = format!(
+        "keys=KEY_A\ndelay_ms=50\ncorrection_step_ms=5\nstate_file={}\n",
+        dir.join("state.json").display()
+    );
+    let mut plugin = start(&dir, &settings, &["-v", "--redact", "pseudonym"]);
+
+    // The second press of KEY_A is a bounce let through, then corrected with backspace.
+    let mut input = plugin.stdin.take().unwrap();
+    send(
+        &mut input,
+        &[
+            KeyEvent::new(KEY_A, PRESS, at(0)),
+            KeyEvent::new(KEY_A, RELEASE, at(50)),
+            KeyEvent::new(KEY_A, PRESS, at(55)),
+            KeyEvent::new(KEY_A, RELEASE, at(60)),
+            KeyEvent::new(KEY_BACKSPACE, PRESS, at(300)),
+        ],
+    );
+    drop(input);
+    let output = plugin.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", output.status);
+
+    let log = String::from_utf8(output.stderr).unwrap();
+    let corrected = log
+        .lines()
+        .find(|line| line.contains("Corrected"))
+        .unwrap_or_else(|| panic!("No correction logged in {log}"));
+    assert!(corrected.contains("key=key-"), "{corrected}");
+    assert!(!log.contains("KEY_A"), "{log}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
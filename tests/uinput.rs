@@ -0,0 +1,312 @@
+//! Runs the daemon against a keyboard made up with uinput and checks what its "Virtual
+//! Keyboard" sends.
+//!
+//! Scripts are written as timelines, `"A down @0ms, A up @3ms, A down @5ms"`, played in real
+//! time so that the kernel stamps events as it would for a real keyboard. The tests are
+//! skipped when `/dev/uinput` cannot be opened, as in most containers and CI runners.
+
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, BusType, Device, EventSummary, InputId, KeyCode, KeyEvent};
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const VENDOR: u16 = 0xdeb0;
+const PRODUCT: u16 = 0x0043;
+/// Sent after every script, and never debounced, to tell when the daemon is done with it.
+const END: KeyCode = KeyCode::KEY_F24;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tests run one at a time, so that each one tells its own virtual keyboard apart.
+static DAEMON: Mutex<()> = Mutex::new(());
+
+/// A key event of a timeline: the key, `down`, `up` or `repeat`, and when to send it.
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    key: KeyCode,
+    value: i32,
+    at: Duration,
+}
+
+/// Parses a timeline such as `"A down @0ms, A up @3ms"`. Keys are evdev names, with or
+/// without their `KEY_` prefix.
+fn timeline(script: &str) -> Vec<Step> {
+    script
+        .split(',')
+        .map(|step| {
+            let words: Vec<&str> = step.split_whitespace().collect();
+            let [key, value, at] = words[..] else {
+                panic!("Expected '<key> <down|up|repeat> @<n>ms', got '{step}'");
+            };
+            let key = KeyCode::from_str(key)
+                .or_else(|_| KeyCode::from_str(&format!("KEY_{key}")))
+                .unwrap_or_else(|_| panic!("Unknown key '{key}'"));
+            let value = match value {
+                "up" => 0,
+                "down" => 1,
+                "repeat" => 2,
+                _ => panic!("Unknown key state '{value}'"),
+            };
+            let at = at
+                .strip_prefix('@')
+                .and_then(|at| at.strip_suffix("ms"))
+                .and_then(|ms| ms.parse().ok())
+                .unwrap_or_else(|| panic!("Expected a time such as '@5ms', got '{at}'"));
+            Step {
+                key,
+                value,
+                at: Duration::from_millis(at),
+            }
+        })
+        .collect()
+}
+
+/// Writes events the way timelines are written, leaving the time out.
+fn describe(events: &[(KeyCode, i32)]) -> String {
+    let steps: Vec<String> = events
+        .iter()
+        .map(|(key, value)| {
+            let name = format!("{key:?}");
+            let state = ["up", "down", "repeat"][*value as usize];
+            format!("{} {state}", name.strip_prefix("KEY_").unwrap_or(&name))
+        })
+        .collect();
+    steps.join(", ")
+}
+
+/// A daemon filtering a made up keyboard, killed when dropped.
+struct Daemon {
+    source: VirtualDevice,
+    output: mpsc::Receiver<(KeyCode, i32)>,
+    process: Child,
+    dir: PathBuf,
+}
+
+impl Daemon {
+    /// Starts the daemon with `settings` added to the config file, or returns `None` when
+    /// uinput is not available.
+    fn start(settings: &str) -> Option<Daemon> {
+        if let Err(e) = OpenOptions::new().write(true).open("/dev/uinput") {
+            eprintln!("Skipping: cannot open /dev/uinput: {e}");
+            return None;
+        }
+
+        let name = format!("Debounce Test Keyboard {}", std::process::id());
+        let mut keys = AttributeSet::<KeyCode>::new();
+        for key in [KeyCode::KEY_A, KeyCode::KEY_B, END] {
+            keys.insert(key);
+        }
+        let mut source = VirtualDevice::builder()
+            .unwrap()
+            .name(&name)
+            .input_id(InputId::new(BusType::BUS_VIRTUAL, VENDOR, PRODUCT, 1))
+            .with_keys(&keys)
+            .unwrap()
+            .build()
+            .unwrap();
+        let source_node = source
+            .enumerate_dev_nodes_blocking()
+            .unwrap()
+            .find_map(Result::ok)
+            .expect("No device node for the test keyboard");
+
+        let dir =
+            std::env::temp_dir().join(format!("debounce-keyboard-uinput-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.ini");
+        std::fs::write(
+            &config,
+            format!(
+                "device_id={}\ndevice_name={name}\ncontrol_socket={}\n{settings}\n",
+                ((VENDOR as u32) << 16) | PRODUCT as u32,
+                dir.join("control.sock").display()
+            ),
+        )
+        .unwrap();
+
+        let before = virtual_keyboards();
+        let process = Command::new(env!("CARGO_BIN_EXE_debounce-keyboard"))
+            .arg("--config-path")
+            .arg(&config)
+            .arg("-q")
+            .stdin(Stdio::null())
+            .spawn()
+            .unwrap();
+        let (sent, output) = mpsc::channel();
+        let mut daemon = Daemon {
+            source,
+            output,
+            process,
+            dir,
+        };
+        let mut keyboard = wait_for(|| {
+            let path = virtual_keyboards()
+                .into_iter()
+                .find(|path| !before.contains(path))?;
+            Device::open(path).ok()
+        });
+        std::thread::spawn(move || {
+            while let Ok(events) = keyboard.fetch_events() {
+                for event in events {
+                    if let EventSummary::Key(_, key, value) = event.destructure()
+                        && sent.send((key, value)).is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        });
+
+        // The daemon grabs the keyboard right after creating its own; until then, events
+        // would reach the rest of the system instead.
+        let mut probe = Device::open(source_node).unwrap();
+        wait_for(|| match probe.grab() {
+            Ok(()) => {
+                probe.ungrab().unwrap();
+                None
+            }
+            Err(_) => Some(()),
+        });
+        assert!(
+            daemon.process.try_wait().unwrap().is_none(),
+            "The daemon exited"
+        );
+        Some(daemon)
+    }
+
+    /// Plays a timeline on the made up keyboard and returns what the virtual keyboard sent.
+    fn play(&mut self, script: &str) -> String {
+        let start = Instant::now();
+        for step in timeline(script) {
+            std::thread::sleep(step.at.saturating_sub(start.elapsed()));
+            self.source
+                .emit(&[*KeyEvent::new(step.key, step.value)])
+                .unwrap();
+        }
+        self.source.emit(&[*KeyEvent::new(END, 1)]).unwrap();
+        self.source.emit(&[*KeyEvent::new(END, 0)]).unwrap();
+
+        let mut events = Vec::new();
+        loop {
+            let event = self
+                .output
+                .recv_timeout(TIMEOUT)
+                .expect("Timed out waiting for the virtual keyboard");
+            if event.0 == END {
+                if event.1 == 0 {
+                    return describe(&events);
+                }
+                continue;
+            }
+            events.push(event);
+        }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Starts a daemon, plays `script` and returns what it let through.
+fn filter(settings: &str, script: &str) -> Option<String> {
+    let _lock = DAEMON.lock().unwrap_or_else(|e| e.into_inner());
+    Some(Daemon::start(settings)?.play(script))
+}
+
+/// Returns the device nodes of every keyboard the daemon created.
+fn virtual_keyboards() -> Vec<PathBuf> {
+    evdev::enumerate()
+        .filter(|(_, device)| device.name() == Some("Virtual Keyboard"))
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Calls `f` until it returns something, failing the test after [`TIMEOUT`].
+fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+    let start = Instant::now();
+    loop {
+        if let Some(value) = f() {
+            return value;
+        }
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "Timed out waiting for the daemon"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn timelines_parse() {
+    assert_eq!(
+        timeline("A down @0ms, KEY_B repeat @12ms"),
+        [
+            Step {
+                key: KeyCode::KEY_A,
+                value: 1,
+                at: Duration::ZERO
+            },
+            Step {
+                key: KeyCode::KEY_B,
+                value: 2,
+                at: Duration::from_millis(12)
+            },
+        ]
+    );
+    assert_eq!(
+        describe(&[(KeyCode::KEY_A, 1), (KeyCode::KEY_A, 0)]),
+        "A down, A up"
+    );
+}
+
+#[test]
+fn bounces_are_suppressed() {
+    let Some(output) = filter(
+        "delay_ms=50\nkeys=KEY_A,KEY_B",
+        "A down @0ms, A up @3ms, A down @5ms, A up @8ms",
+    ) else {
+        return;
+    };
+    assert_eq!(output, "A down, A up");
+}
+
+#[test]
+fn presses_further_apart_than_the_delay_pass() {
+    let Some(output) = filter(
+        "delay_ms=50\nkeys=KEY_A,KEY_B",
+        "A down @0ms, A up @30ms, A down @150ms, A up @180ms",
+    ) else {
+        return;
+    };
+    assert_eq!(output, "A down, A up, A down, A up");
+}
+
+#[test]
+fn other_keys_pass_unchanged() {
+    let Some(output) = filter(
+        "delay_ms=50\nkeys=KEY_A",
+        "B down @0ms, B up @3ms, B down @5ms, A down @6ms, B up @8ms, A up @9ms",
+    ) else {
+        return;
+    };
+    assert_eq!(output, "B down, B up, B down, A down, B up, A up");
+}
+
+#[test]
+fn held_keys_repeat() {
+    let Some(output) = filter(
+        "delay_ms=50\nkeys=KEY_A",
+        "A down @0ms, A repeat @100ms, A repeat @130ms, A up @140ms",
+    ) else {
+        return;
+    };
+    assert_eq!(output, "A down, A repeat, A repeat, A up");
+}